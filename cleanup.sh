@@ -1 +1 @@
-rm *.hlt *.log
+rm *.hlt *.log trace-* transcript-*
@@ -1,2 +1,2 @@
-cargo build
+cargo build --features trace
 .\halite.exe -d "30 30" "target/debug/MyBot" "target/debug/RandomBot"
@@ -1,4 +1,4 @@
 #!/bin/bash
 
-cargo build
+cargo build --features trace
 ./halite -d "30 30" "target/debug/MyBot" "target/debug/RandomBot"
@@ -1,13 +1,51 @@
+mod budget;
+mod capture;
+mod converge;
+mod export;
+mod frontier;
 mod hlt;
+mod mine;
+mod patterns;
+mod policy;
+mod replay;
+mod routing;
+#[cfg(test)]
+mod classify_coverage;
+mod scenario;
+mod scoring;
+mod state;
+mod sim;
+mod statics;
+#[cfg(test)]
+mod puzzles;
+#[cfg(test)]
+mod testmaps;
+mod trace;
+mod validate;
+mod waste;
+mod window;
 use hlt::networking;
 use hlt::types::*;
-use std::collections::{HashMap, HashSet};
+use budget::Budget;
+use converge::Schedule;
+use frontier::Frontier;
+use policy::Policy;
+use scoring::Weights;
+use state::BotState;
+use statics::MapStatics;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::btree_map::Entry;
+use std::any::Any;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
 
 struct MoveFeatures {
     loc: Location,
     d: u8,
     owner_them: u8,
     distance: i32,
+    closest: bool, // No other direction reaches the border sooner
     friendly: bool,
     strength_us: i32,
     strength_them: i32,
@@ -15,6 +53,52 @@ struct MoveFeatures {
     assigned_strength: i32,
     production_them: i32,
     production_us: i32,
+    enemy_strength_around_them: i32, // What overkill could hit if we take the target
+}
+
+impl MoveFeatures {
+    // The values scoring::FEATURES names, for weighing against each other.
+    fn values(&self, width: u16) -> [f32; scoring::FEATURE_COUNT] {
+        use scoring::*;
+        let flag = |b: bool| if b { 1.0 } else { 0.0 };
+        let strength = |s: i32| s as f32 / 255.0;
+        let production = |p: i32| p as f32 / 32.0;
+        let mut v = [0.0; FEATURE_COUNT];
+        v[BIAS] = 1.0;
+        v[STRENGTH_US] = strength(self.strength_us);
+        v[PRODUCTION_US] = production(self.production_us);
+        if self.d == STILL {
+            // Only bias, still, strength_us and production_us apply to staying.
+            v[STAY] = 1.0;
+            return v
+        }
+        let fight = !self.friendly;
+        let captures = fight && self.strength_us > self.strength_them;
+        v[FRIENDLY] = flag(self.friendly);
+        v[ENEMY] = flag(fight && self.owner_them != 0);
+        v[NEUTRAL] = flag(self.owner_them == 0);
+        v[NOT_CLOSEST_BORDER] = flag(!self.closest);
+        v[DISTANCE] = self.distance as f32 / width as f32;
+        v[STRENGTH_THEM] = strength(self.strength_them);
+        v[PRODUCTION_THEM] = production(self.production_them);
+        v[ADJACENT_STRENGTH_US] = strength(self.adjacent_strength_us);
+        v[ASSIGNED_STRENGTH] = strength(self.assigned_strength);
+        v[WEAK] = flag(self.strength_us <= self.production_us * 5);
+        v[LOSING_BATTLE] = flag(self.strength_us <= self.strength_them + self.production_them);
+        v[OVER_MERGE_CAP] = flag(self.friendly && self.strength_us + self.strength_them > 260);
+        v[OVER_ASSIGNED_CAP] = flag(self.strength_us + self.assigned_strength > 260);
+        v[CAPTURES] = flag(captures);
+        if fight {
+            v[STRENGTH_LOST] = strength(self.strength_us.min(self.strength_them));
+        }
+        if captures {
+            v[OVERKILL] = strength(self.enemy_strength_around_them.min(self.strength_us - self.strength_them));
+        }
+        if self.friendly {
+            v[MERGE_WASTE] = strength((self.strength_us + self.strength_them - 255).max(0));
+        }
+        v
+    }
 }
 
 fn get_total_adjacent_strength(loc: Location, map: &GameMap, my_id: u8) -> i32 {
@@ -28,60 +112,89 @@ fn get_total_adjacent_strength(loc: Location, map: &GameMap, my_id: u8) -> i32 {
     total_adjacent_strength
 }
 
-fn get_best_move_simple(
-    loc: Location,
-    map: &GameMap,
-    my_id: u8) -> u8  {
+// The first of the default weights' hard penalties that rules out a candidate, if any.
+fn rejected_by(a: &MoveFeatures) -> Option<trace::Filter> {
+    use trace::Filter::*;
+    if a.strength_us <= a.production_us * 5 {
+        // Don't move weak pieces
+        Some(WeakPiece)
+    } else if !a.closest {
+        // Only move towards the closest border
+        Some(NotClosestBorder)
+    } else if a.strength_us <= a.strength_them + a.production_them {
+        // Don't allow losing battles
+        Some(LosingBattle)
+    } else if a.friendly && a.strength_us + a.strength_them > 260 {
+        // Allow a small loss so full strength don't get stuck
+        Some(MergeCap)
+    } else if a.strength_us + a.assigned_strength > 260 {
+        // Don't allow too many troops to move into the same space
+        Some(AssignedCap)
+    } else {
+        None
+    }
+}
+
+// Staying and each direction from loc, in DIRECTIONS order.
+fn candidates(loc: Location, map: &GameMap, my_id: u8) -> Vec<MoveFeatures> {
+    let current = map.get_site_ref(loc, STILL);
     let mut moves = vec![];
-    for d in &CARDINALS {
+    for d in &DIRECTIONS {
         let proposed_loc = map.get_location(loc, *d);
         let proposed = map.get_site_ref(loc, *d);
-        let current = map.get_site_ref(loc, STILL);
         let already_assigned_strength: i32 = 0;
         moves.push(MoveFeatures {
-            loc: loc,
+            loc,
             d: *d,
-            distance: distance_to_border(loc, *d, &map, my_id),
+            distance: if *d == STILL { 0 } else { distance_to_border(loc, *d, map, my_id) },
+            closest: true,
             owner_them: proposed.owner,
             friendly: proposed.owner == my_id,
             strength_us: current.strength as i32,
             strength_them: proposed.strength as i32,
-            adjacent_strength_us: get_total_adjacent_strength(proposed_loc, &map, my_id),
+            adjacent_strength_us: get_total_adjacent_strength(proposed_loc, map, my_id),
             assigned_strength: already_assigned_strength,
             production_us: current.production as i32,
             production_them: proposed.production as i32,
+            enemy_strength_around_them: CARDINALS.iter()
+                .map(|d| map.get_site_ref(proposed_loc, *d))
+                .filter(|s| s.owner != my_id && s.owner != 0)
+                .map(|s| s.strength as i32)
+                .sum(),
         });
     }
-    // Sort by distance
-    moves.sort_by(|a, b| a.distance.cmp(&b.distance));
-    // Remove all but the shortest distances and filter losing battles and strength losses
-    let shortest = moves[0].distance;
-    let mut moves: Vec<MoveFeatures> = moves.into_iter()
-        // Don't move weak pieces
-        .filter(|a| a.strength_us > a.production_us * 5)
-        // Only move towards the closest border
-        .filter(|a| a.distance == shortest)
-        // Don't allow losing battles
-        .filter(|a| a.strength_us > (a.strength_them + a.production_them))
-        // Allow a small loss so full strength don't get stuck
-        .filter(|a| !a.friendly || a.strength_us + a.strength_them <= 260)
-        // Don't allow too many troops to move into the same space
-        .filter(|a| a.strength_us + a.assigned_strength <= 260)
-        .collect();
-    if moves.len() == 0 {
-        return STILL
-    }
-    // If there's still more than one move available, go for production
-    moves.sort_by(|a, b| a.production_them.cmp(&b.production_them));
-    // Prefer losing less strength
-    moves.sort_by(|a, b| b.strength_them.cmp(&a.strength_them));
-
-    let m = moves.pop().unwrap();
-    m.d
+    let shortest = moves[1..].iter().map(|a| a.distance).min().unwrap();
+    for a in &mut moves[1..] {
+        a.closest = a.distance == shortest;
+    }
+    moves
+}
+
+// Scores staying and each direction with the weights and takes the best, the
+// later direction on ties.
+fn get_best_move_simple(
+    loc: Location,
+    map: &GameMap,
+    my_id: u8,
+    weights: &Weights,
+    trace: &mut trace::TurnTrace) -> u8  {
+    let moves = candidates(loc, map, my_id);
+    let scores = moves.iter().map(|a| weights.score(&a.values(map.width))).collect::<Vec<_>>();
+    for (a, score) in moves.iter().zip(&scores).skip(1) {
+        if *score <= scores[0] {
+            if let Some(f) = rejected_by(a) {
+                trace.reason(loc, trace::Reason::Rejected(a.d, f));
+            }
+        }
+    }
+    let best = (0..moves.len())
+        .max_by(|a, b| scores[*a].partial_cmp(&scores[*b]).unwrap_or(::std::cmp::Ordering::Equal))
+        .unwrap();
+    moves[best].d
 }
 
 fn distance_to_border(loc: Location, dir: u8, map: &GameMap, my_id: u8) -> i32 {
-    let mut l = loc.clone();
+    let mut l = loc;
     let mut counter = 0;
     loop {
         if map.get_site_ref(l, STILL).owner != my_id {
@@ -109,8 +222,9 @@ fn get_units_of_player(id: u8, map: &GameMap) -> Vec<Location> {
     result
 }
 
-fn max_capture_strategy(game_map: &GameMap, my_id: u8) -> HashMap<Location, u8> {
-    let my_units = get_units_of_player(my_id, &game_map);
+fn max_capture_strategy(game_map: &GameMap, my_id: u8, trace: &mut trace::TurnTrace, budget: &Budget) -> MoveSet {
+    use trace::Reason::*;
+    let my_units = get_units_of_player(my_id, game_map);
     let mut possibilities = my_units
         .iter()
         .flat_map(|l| {
@@ -124,16 +238,18 @@ fn max_capture_strategy(game_map: &GameMap, my_id: u8) -> HashMap<Location, u8>
             let current = game_map.get_site_ref(l, STILL);
             MoveFeatures {
                 loc: l,
-                d: d,
+                d,
                 distance: 1,
+                closest: true,
                 owner_them: proposed.owner,
                 friendly: proposed.owner == my_id,
                 strength_us: current.strength as i32,
                 strength_them: proposed.strength as i32,
-                adjacent_strength_us: get_total_adjacent_strength(l, &game_map, my_id),
+                adjacent_strength_us: get_total_adjacent_strength(l, game_map, my_id),
                 assigned_strength: 0,
                 production_us: current.production as i32,
                 production_them: proposed.production as i32,
+                enemy_strength_around_them: 0,
             }
         })
         // Don't move weak pieces
@@ -142,33 +258,40 @@ fn max_capture_strategy(game_map: &GameMap, my_id: u8) -> HashMap<Location, u8>
         .filter(|a| !a.friendly)
         .filter(|a| a.adjacent_strength_us + a.strength_us > a.strength_them)
         .collect::<Vec<_>>();
-    possibilities.sort_by(|a, b| b.strength_them.cmp(&a.strength_them));
-    possibilities.sort_by(|a, b| a.strength_us.cmp(&b.strength_us));
-    possibilities.sort_by(|a, b| a.production_them.cmp(&b.production_them));
+    possibilities.sort_by_key(|a| -a.strength_them);
+    possibilities.sort_by_key(|a| a.strength_us);
+    possibilities.sort_by_key(|a| a.production_them);
 
-    let mut moves = HashMap::new();
-    while possibilities.len() > 0 {
-        let m = possibilities.pop().unwrap();
+    let mut moves = MoveSet::new();
+    while let Some(m) = possibilities.pop() {
+        // Units left over when time runs out stay still below.
+        if budget.exhausted() {
+            break
+        }
         if moves.contains_key(&m.loc) {
             continue
         }
         if m.strength_us > m.strength_them {
             // If we can capture, do so
+            trace.reason(m.loc, Branch("MaxCapture.capture"));
             moves.insert(m.loc, m.d);
         } else {
             // Otherwise, move everything towards that point
             for adj in &CARDINALS {
                 let adj_loc = game_map.get_location(m.loc, *adj);
-                if !moves.contains_key(&adj_loc) {
-                    moves.insert(adj_loc, reverse(*adj));
+                if let Entry::Vacant(e) = moves.entry(adj_loc) {
+                    trace.reason(adj_loc, Branch("MaxCapture.gather"));
+                    e.insert(reverse(*adj));
                 }
             }
+            trace.reason(m.loc, Branch("MaxCapture.gather_point"));
             moves.insert(m.loc, STILL);
         }
     }
     for remaining in my_units {
-        if !moves.contains_key(&remaining) {
-            moves.insert(remaining, STILL);
+        if let Entry::Vacant(e) = moves.entry(remaining) {
+            trace.reason(remaining, Branch("MaxCapture.no_target"));
+            e.insert(STILL);
         }
     }
     moves
@@ -184,46 +307,8 @@ fn reverse(d: u8) -> u8 {
     }
 }
 
-fn find_poi(map: &GameMap, my_id: u8) -> Vec<Location> {
-    let mut total = 0f32;
-    for a in 0..map.height {
-        for b in 0..map.width {
-            let l = Location { x: b, y: a };
-            let site = map.get_site_ref(l, STILL);
-            total += site.production as f32;
-        }
-    }
-    let avg_production = total / (map.height as f32 * map.width as f32);
-    let avg_production = avg_production as u8;
-    let mut result = Vec::new();
-    for a in 0..map.height {
-        for b in 0..map.width {
-            let l = Location { x: b, y: a };
-            let site = map.get_site_ref(l, STILL);
-            if site.production >= avg_production * 2 && site.owner != my_id {
-                result.push(l);
-            }
-        }
-    }
-    result
-}
-
-fn find_closest_poi(l: Location, map: &GameMap, poi: &Vec<Location>) -> Location {
-    poi.iter()
-    .min_by_key(|p| map.get_distance(l, **p)).unwrap_or(&Location{x: 0, y: 0}).clone()
-}
-
-use std::io::prelude::*;
-use std::fs::OpenOptions;
-fn log(s: String, id: u8) {
-    let mut file = OpenOptions::new()
-         .append(true)
-         .create(true)
-         .open(format!("output-{}.log", id))
-         .unwrap();
-    file.write(s.as_bytes()).unwrap();
-}
-
+// Pincer targets are only read by tests now that capture::plan makes the captures.
+#[cfg_attr(not(test), allow(dead_code))]
 #[derive(Copy, Clone)]
 enum Troop {
     Interior(Location), // Surrounded by at least 1 square of friendly
@@ -238,139 +323,249 @@ enum Troop {
     Unknown(Location),
 }
 
-fn troop_strategy(map: &GameMap, my_id: u8) -> HashMap<Location, u8> {
+impl Troop {
+    fn name(&self) -> &'static str {
+        use Troop::*;
+        match *self {
+            Interior(_) => "Interior",
+            VerticalWall(_) => "VerticalWall",
+            HorizontalWall(_) => "HorizontalWall",
+            Pincer(..) => "Pincer",
+            Pincer3(..) => "Pincer3",
+            Lance(_) => "Lance",
+            Island(_) => "Island",
+            Reinforcement(_) => "Reinforcement",
+            Corner(..) => "Corner",
+            Unknown(_) => "Unknown",
+        }
+    }
+
+    // Our own units taking part, leaving out any target.
+    fn members(&self) -> Vec<Location> {
+        use Troop::*;
+        match *self {
+            Interior(l) | VerticalWall(l) | HorizontalWall(l) | Lance(l) | Island(l)
+                | Reinforcement(l) | Unknown(l) => vec![l],
+            Pincer(l1, l2, _) => vec![l1, l2],
+            Pincer3(l1, l2, l3, _) => vec![l1, l2, l3],
+            Corner(l1, l2, l3) => vec![l1, l2, l3],
+        }
+    }
+}
+
+// Starts from get_best_move_simple for every unit, then decides troop by troop
+// while the budget lasts. Planning steps are skipped once it's nearly spent and
+// any troop left over takes its simple move.
+fn troop_strategy(map: &GameMap, my_id: u8, trace: &mut trace::TurnTrace, budget: &Budget, statics: &MapStatics, weights: &Weights, schedule: &mut Schedule) -> MoveSet {
     use Troop::*;
+    use trace::Reason::*;
     let my_units = get_units_of_player(my_id, map);
+    // Cheap enough to always have, and only traced for the units that end up using it.
+    let simple = my_units.iter()
+        .map(|l| (*l, get_best_move_simple(*l, map, my_id, weights, &mut trace::TurnTrace::new(0))))
+        .collect::<MoveSet>();
     let troops = classify(my_units, map, my_id);
-    let mut moves = HashMap::new();
-    let poi = find_poi(map, my_id);
+    let mut moves = MoveSet::new();
+    let frontier = Frontier::new(map, my_id, statics);
+    let plans = if budget.nearly_exhausted() { Vec::new() } else { capture::plan(map, my_id, &frontier) };
+    let converging = if budget.nearly_exhausted() {
+        // Plans count turns, so one without a step is lost.
+        schedule.clear();
+        Vec::new()
+    } else {
+        schedule.step(map, my_id, &frontier, statics, &plans)
+    };
+    let planned = plans.iter().flat_map(|p| p.moves.iter().map(|m| m.0))
+        .chain(converging.iter().map(|m| m.0))
+        .collect::<HashSet<_>>();
+    let supply = troops.iter()
+        .filter_map(|t| match *t {
+            Interior(l) | Reinforcement(l) => Some(l),
+            _ => None,
+        })
+        .filter(|l| {
+            let site = map.get_site_ref(*l, STILL);
+            site.strength >= site.production * 5 && !planned.contains(l)
+        })
+        .collect::<Vec<_>>();
+    let routes = if budget.nearly_exhausted() { BTreeMap::new() } else { routing::route(map, my_id, &frontier, statics, &supply) };
+    // Of two moves from l, the one onto the target that pays back sooner. The
+    // first wins ties.
+    let by_payback = |l: Location, a: u8, b: u8| frontier.payback(map.get_location(l, a))
+        .partial_cmp(&frontier.payback(map.get_location(l, b)))
+        .unwrap();
     let mut assigned_strength: HashMap<Location, usize> = HashMap::new();
-    let mut commit_move = |moves: &mut HashMap<Location, u8>, l, d| {
+    let mut held = Vec::new();
+    // A unit keeps the first move it's given, so capture plans win over troops.
+    // Like routing::route, a site holds what's sent there plus its own unit
+    // unless that unit is moving or routed away.
+    let mut commit_move = |moves: &mut MoveSet, l, d| {
+        if moves.contains_key(&l) {
+            return
+        }
+        if d == STILL {
+            moves.insert(l, STILL);
+            return
+        }
         let proposed = map.get_site_ref(l, d);
         let proposed_loc = map.get_location(l, d);
-        let strength = map.get_site_ref(l, STILL).strength;
-        if proposed.owner == my_id && strength as u16 + proposed.strength as u16 > 260u16 {
-            moves.insert(l, STILL);
-        } else if proposed.owner == my_id && strength as usize + *assigned_strength.entry(proposed_loc).or_insert(0) > 260 {
+        let strength = map.get_site_ref(l, STILL).strength as usize;
+        let leaves = moves.get(&proposed_loc).or_else(|| routes.get(&proposed_loc)).map_or(false, |d| *d != STILL);
+        let staying = if leaves { 0 } else { proposed.strength as usize };
+        let arriving = *assigned_strength.get(&proposed_loc).unwrap_or(&0);
+        if proposed.owner == my_id && strength + staying + arriving > 260 {
+            held.push(l);
             moves.insert(l, STILL);
         } else {
-            *assigned_strength.entry(proposed_loc).or_insert(0) += strength as usize;
-            *assigned_strength.entry(l).or_insert(strength as usize) -= strength as usize;
+            *assigned_strength.entry(proposed_loc).or_insert(0) += strength;
             moves.insert(l, d);
         }
     };
+    for p in &plans {
+        for &(l, d) in &p.moves {
+            trace.reason(l, Branch(if p.now { "Capture.now" } else { "Capture.gather" }));
+            commit_move(&mut moves, l, d);
+        }
+    }
+    for &(l, d) in &converging {
+        trace.reason(l, Branch(if d == STILL { "Converge.wait" } else { "Converge.move" }));
+        commit_move(&mut moves, l, d);
+    }
     for t in troops {
+        for l in t.members() {
+            trace.unit(l, t.name());
+        }
+        if budget.nearly_exhausted() {
+            // Not enough time left to think, take the move worked out up front.
+            for l in t.members() {
+                if !moves.contains_key(&l) {
+                    trace.reason(l, Branch("Budget.emergency"));
+                    commit_move(&mut moves, l, simple[&l]);
+                }
+            }
+            continue
+        }
+        if t.members().iter().any(|l| moves.contains_key(l)) {
+            // Part of the troop went to a capture or convergence, the rest moves on its own.
+            for l in t.members() {
+                if !moves.contains_key(&l) {
+                    trace.reason(l, Branch("Capture.leftover"));
+                    commit_move(&mut moves, l, get_best_move_simple(l, map, my_id, weights, trace));
+                }
+            }
+            continue
+        }
         match t {
             Interior(l) | Reinforcement(l) => {
                 let site = map.get_site_ref(l, STILL);
                 if site.strength < site.production * 5 {
+                    trace.reason(l, Branch("Interior.weak"));
                     commit_move(&mut moves, l, STILL);
                 } else {
-                    let closest = find_closest_poi(l, map, &poi);
-                    let d = map.get_direction(l, closest);
-                    let proposed = map.get_site_ref(l, d);
-                    if site.strength as u16 + proposed.strength as u16 > 260u16  {
-                        commit_move(&mut moves, l, STILL);
+                    let d = *routes.get(&l).unwrap_or(&STILL);
+                    if d == STILL {
+                        trace.reason(l, Branch("Interior.merge_cap"));
                     } else {
-                        commit_move(&mut moves, l, d);
+                        trace.reason(l, Branch("Interior.to_target"));
                     }
+                    commit_move(&mut moves, l, d);
                 }
             },
             VerticalWall(l) => {
                 let site = map.get_site_ref(l, STILL);
-                let left = map.get_site_ref(l, WEST);
-                let right = map.get_site_ref(l, EAST);
-                if left.owner != my_id && site.strength > left.strength {
-                    commit_move(&mut moves, l, WEST);
-                } else if right.owner != my_id && site.strength > right.strength {
-                    commit_move(&mut moves, l, EAST);
+                let attack = [WEST, EAST].iter().cloned()
+                    .filter(|d| {
+                        let enemy = map.get_site_ref(l, *d);
+                        enemy.owner != my_id && site.strength > enemy.strength
+                    })
+                    .min_by(|a, b| by_payback(l, *a, *b));
+                if let Some(d) = attack {
+                    trace.reason(l, Branch("VerticalWall.attack"));
+                    commit_move(&mut moves, l, d);
                 } else if site.strength < site.production * 5 {
+                    trace.reason(l, Branch("VerticalWall.weak"));
                     commit_move(&mut moves, l, STILL);
                 } else {
                     let up = map.get_site_ref(l, NORTH);
                     let down = map.get_site_ref(l, SOUTH);
                     if up.strength > site.strength {
+                        trace.reason(l, Branch("VerticalWall.join_stronger"));
                         commit_move(&mut moves, l, NORTH);
                     } else if down.strength > site.strength {
+                        trace.reason(l, Branch("VerticalWall.join_stronger"));
                         commit_move(&mut moves, l, SOUTH);
                     } else {
+                        trace.reason(l, Branch("VerticalWall.hold"));
                         commit_move(&mut moves, l, STILL);
                     }
                 }
             },
             HorizontalWall(l) => {
                 let site = map.get_site_ref(l, STILL);
-                let up = map.get_site_ref(l, NORTH);
-                let down = map.get_site_ref(l, SOUTH);
-                if up.owner != my_id && site.strength > up.strength {
-                    commit_move(&mut moves, l, NORTH);
-                } else if down.owner != my_id && site.strength > down.strength {
-                    commit_move(&mut moves, l, SOUTH);
+                let attack = [NORTH, SOUTH].iter().cloned()
+                    .filter(|d| {
+                        let enemy = map.get_site_ref(l, *d);
+                        enemy.owner != my_id && site.strength > enemy.strength
+                    })
+                    .min_by(|a, b| by_payback(l, *a, *b));
+                if let Some(d) = attack {
+                    trace.reason(l, Branch("HorizontalWall.attack"));
+                    commit_move(&mut moves, l, d);
                 } else if site.strength < site.production * 5 {
+                    trace.reason(l, Branch("HorizontalWall.weak"));
                     commit_move(&mut moves, l, STILL);
                 } else {
                     let left = map.get_site_ref(l, WEST);
                     let right = map.get_site_ref(l, EAST);
                     if left.strength > site.strength {
+                        trace.reason(l, Branch("HorizontalWall.join_stronger"));
                         commit_move(&mut moves, l, WEST);
                     } else if right.strength > site.strength {
+                        trace.reason(l, Branch("HorizontalWall.join_stronger"));
                         commit_move(&mut moves, l, EAST);
                     } else {
+                        trace.reason(l, Branch("HorizontalWall.hold"));
                         commit_move(&mut moves, l, STILL);
                     }
                 }
             },
-            Pincer(l1, l2, e) => {
-                let site1 = map.get_site_ref(l1, STILL);
-                let site2 = map.get_site_ref(l2, STILL);
-                let enemy = map.get_site_ref(e, STILL);
-                if site1.strength + site2.strength > enemy.strength {
-                    commit_move(&mut moves, l1, map.get_direction(l1, e));
-                    commit_move(&mut moves, l2, map.get_direction(l2, e));
-                } else {
-                    commit_move(&mut moves, l1, STILL);
-                    commit_move(&mut moves, l2, STILL);
-                }
-            },
-            Pincer3(l1, l2, l3, e) => {
-                let site1 = map.get_site_ref(l1, STILL);
-                let site2 = map.get_site_ref(l2, STILL);
-                let site3 = map.get_site_ref(l3, STILL);
-                let enemy = map.get_site_ref(e, STILL);
-                if site1.strength + site2.strength > enemy.strength {
-                    commit_move(&mut moves, l1, map.get_direction(l1, e));
-                    commit_move(&mut moves, l2, map.get_direction(l2, e));
-                } else if site3.strength + site2.strength > enemy.strength {
-                    commit_move(&mut moves, l3, map.get_direction(l3, e));
-                    commit_move(&mut moves, l2, map.get_direction(l2, e));
-                } else if site3.strength + site2.strength + site1.strength > enemy.strength {
-                    commit_move(&mut moves, l1, map.get_direction(l1, e));
-                    commit_move(&mut moves, l2, map.get_direction(l2, e));
-                    commit_move(&mut moves, l3, map.get_direction(l3, e));
-                } else {
-                    commit_move(&mut moves, l1, STILL);
-                    commit_move(&mut moves, l2, STILL);
-                    commit_move(&mut moves, l3, STILL);
+            Pincer(..) | Pincer3(..) => {
+                // Whatever they could take together, capture::plan would have.
+                for l in t.members() {
+                    trace.reason(l, Branch("Pincer.too_weak"));
+                    commit_move(&mut moves, l, STILL);
                 }
             },
             Lance(l) => {
                 let site = map.get_site_ref(l, STILL);
-                for d in &CARDINALS {
-                    let enemy = map.get_site_ref(l, *d);
-                    if enemy.owner != my_id && site.strength > enemy.strength {
-                        commit_move(&mut moves, l, *d);
-                        break;
-                    }
+                let target = CARDINALS.iter().cloned()
+                    .filter(|d| {
+                        let enemy = map.get_site_ref(l, *d);
+                        enemy.owner != my_id && site.strength > enemy.strength
+                    })
+                    .min_by(|a, b| by_payback(l, *a, *b));
+                match target {
+                    Some(d) => {
+                        trace.reason(l, Branch("Lance.attack"));
+                        commit_move(&mut moves, l, d);
+                    },
+                    None => trace.reason(l, Branch("Lance.no_target")),
                 }
             },
             Island(l) => {
                 let site = map.get_site_ref(l, STILL);
-                for d in &CARDINALS {
-                    let enemy = map.get_site_ref(l, *d);
-                    if site.strength > enemy.strength {
-                        commit_move(&mut moves, l, *d);
-                        break;
-                    }
+                let target = CARDINALS.iter().cloned()
+                    .filter(|d| {
+                        let enemy = map.get_site_ref(l, *d);
+                        site.strength > enemy.strength
+                    })
+                    .min_by(|a, b| by_payback(l, *a, *b));
+                match target {
+                    Some(d) => {
+                        trace.reason(l, Branch("Island.attack"));
+                        commit_move(&mut moves, l, d);
+                    },
+                    None => trace.reason(l, Branch("Island.no_target")),
                 }
             },
             Corner(l, c, r) => {
@@ -383,32 +578,46 @@ fn troop_strategy(map: &GameMap, my_id: u8) -> HashMap<Location, u8> {
                 let enemy2 = map.get_site_ref(c, e2);
 
                 if sitec.strength > enemy1.strength {
-                    moves.insert(c, e1);
-                    moves.insert(r, map.get_direction(r, c));
-                    moves.insert(l, STILL);
+                    trace.reason(c, Branch("Corner.attack_away_from_right"));
+                    trace.reason(r, Branch("Corner.attack_away_from_right"));
+                    trace.reason(l, Branch("Corner.attack_away_from_right"));
+                    commit_move(&mut moves, c, e1);
+                    commit_move(&mut moves, r, map.get_direction(r, c));
+                    commit_move(&mut moves, l, STILL);
                 } else if sitec.strength > enemy2.strength {
-                    moves.insert(c, e2);
-                    moves.insert(l, map.get_direction(l, c));
-                    moves.insert(r, STILL);
+                    trace.reason(c, Branch("Corner.attack_away_from_left"));
+                    trace.reason(l, Branch("Corner.attack_away_from_left"));
+                    trace.reason(r, Branch("Corner.attack_away_from_left"));
+                    commit_move(&mut moves, c, e2);
+                    commit_move(&mut moves, l, map.get_direction(l, c));
+                    commit_move(&mut moves, r, STILL);
                 } else {
-                    moves.insert(c, STILL);
+                    trace.reason(c, Branch("Corner.gather"));
+                    commit_move(&mut moves, c, STILL);
                     if siter.strength > siter.production * 5 {
-                        moves.insert(r, map.get_direction(r, c));
+                        trace.reason(r, Branch("Corner.gather"));
+                        commit_move(&mut moves, r, map.get_direction(r, c));
                     } else {
-                        moves.insert(r, STILL);
+                        trace.reason(r, Branch("Corner.weak"));
+                        commit_move(&mut moves, r, STILL);
                     }
                     if sitel.strength > sitel.production * 5 {
-                        moves.insert(l, map.get_direction(l, c));
+                        trace.reason(l, Branch("Corner.gather"));
+                        commit_move(&mut moves, l, map.get_direction(l, c));
                     } else {
-                        moves.insert(l, STILL);
+                        trace.reason(l, Branch("Corner.weak"));
+                        commit_move(&mut moves, l, STILL);
                     }
                 }
             },
             Unknown(l) => {
-                commit_move(&mut moves, l, get_best_move_simple(l, map, my_id));
+                commit_move(&mut moves, l, get_best_move_simple(l, map, my_id, weights, trace));
             },
         }
     }
+    for l in held {
+        trace.reason(l, Held);
+    }
     moves
 }
 
@@ -440,110 +649,356 @@ fn classify(locs: Vec<Location>, map: &GameMap, my_id: u8) -> Vec<Troop> {
     result
 }
 
+// The troop for the first formation in patterns::PATTERNS that fits around loc.
 fn classify_loc(loc: Location, map: &GameMap, my_id: u8) -> Troop {
-    use Troop::*;
-    let get_location = |l, d1, d2| {
-        map.get_location(map.get_location(l, d1), d2)
-    };
-    let friendly = |l| { if map.get_site_ref(l, STILL).owner == my_id {1} else {0} };
-
-    // nw nn ne
-    // ww    ee
-    // sw ss se
-    let (nw, nn, ne) = (get_location(loc, WEST, NORTH), map.get_location(loc, NORTH), get_location(loc, EAST, NORTH));
-    let (ww, ee) = (map.get_location(loc, WEST), map.get_location(loc, EAST));
-    let (sw, ss, se) = (get_location(loc, WEST, SOUTH), map.get_location(loc, SOUTH), get_location(loc, EAST, SOUTH));
-
-    let surroundings = (friendly(nw), friendly(nn), friendly(ne),
-                       friendly(ww),               friendly(ee),
-                       friendly(sw), friendly(ss), friendly(se));
-    match surroundings {
-        (a, b, c,
-         d,    e,
-         f, g, h) if a > 1 || b > 1 || c > 1 || d > 1 || e > 1 || f > 1 || g > 1 || h > 1 => panic!("Bad Map"),
-        (1, 1, 1,
-         1,    1,
-         1, 1, 1) => Interior(loc),
-        (_, 1, _,
-         1,    1,
-         _, 1, _) => Reinforcement(loc),
-        (_, 0, _,
-         0,    0,
-         _, 0, _) => Island(loc),
-        (_, a, _,
-         d,    b,
-         _, c, _) if a + b + c + d == 1 => Lance(loc),
-        (_, 1, _,
-         a,    b,
-         _, 1, _) if a + b < 2 => VerticalWall(loc),
-        (_, a, _,
-         1,    1,
-         _, b, _) if a + b < 2 => HorizontalWall(loc),
-        (1, 0, 1,
-         _,    _,
-         _, _, _) => Pincer3(nw, loc, ne, nn),
-        (1, _, _,
-         0,    _,
-         1, _, _) => Pincer3(nw, loc, sw, ww),
-        (_, _, 1,
-         _,    0,
-         _, _, 1) => Pincer3(ne, loc, se, ee),
-        (_, _, _,
-         _,    _,
-         1, 0, 1) => Pincer3(sw, loc, se, ss),
-
-        (1, 0, _,
-         _,    _,
-         _, _, _) => Pincer(nw, loc, nn),
-        (_, _, _,
-         0,    _,
-         1, _, _) => Pincer(loc, sw, ww),
-        (_, _, 1,
-         _,    0,
-         _, _, _) => Pincer(ne, loc, ee),
-        (_, _, _,
-         _,    _,
-         1, 0, _) => Pincer(sw, loc, ss),
-        (_, 0, 1,
-         _,    _,
-         _, _, _) => Pincer(loc, ne, nn),
-        (1, _, _,
-         0,    _,
-         _, _, _) => Pincer(nw, loc, ww),
-        (_, _, _,
-         _,    0,
-         _, _, 1) => Pincer(loc, se, ee),
-        (_, _, _,
-         _,    _,
-         _, 0, 1) => Pincer(loc, se, ss),
-
-        (_, 0, _,
-         0,    1,
-         _, 1, _) => Corner(ss, loc, ee),
-        (_, 0, _,
-         1,    0,
-         _, 1, _) => Corner(ww, loc, ss),
-        (_, 1, _,
-         0,    1,
-         _, 0, _) => Corner(nn, loc, ee),
-        (_, 1, _,
-         1,    0,
-         _, 0, _) => Corner(ww, loc, nn),
-        _ => Unknown(loc),
+    match patterns::find(loc, map, my_id) {
+        Some(m) => troop(&m, loc),
+        None => Troop::Unknown(loc),
+    }
+}
+
+fn troop(m: &patterns::Match, loc: Location) -> Troop {
+    use patterns::Role;
+    let (s, e) = (&m.members, m.target.unwrap_or(loc));
+    match m.role {
+        Role::Interior => Troop::Interior(loc),
+        Role::VerticalWall => Troop::VerticalWall(loc),
+        Role::HorizontalWall => Troop::HorizontalWall(loc),
+        Role::Pincer => Troop::Pincer(s[0], s[1], e),
+        Role::Pincer3 => Troop::Pincer3(s[0], s[1], s[2], e),
+        Role::Lance => Troop::Lance(loc),
+        Role::Island => Troop::Island(loc),
+        Role::Reinforcement => Troop::Reinforcement(loc),
+        Role::Corner => Troop::Corner(s[0], s[1], s[2]),
+    }
+}
+
+// Sets up recording or replay of the engine's input and returns the turn to
+// stop after when replaying. The engine never passes arguments, so:
+//   A2I_RECORD=<path> copies everything read from the engine into a transcript,
+//     {pid} in the path is replaced. Builds with the record feature always record,
+//     to transcript-{pid}.txt unless told otherwise.
+//   MyBot --replay <path> [--turn <n>] [--dump] reads a transcript instead of stdin
+//     and exits after turn n, so one turn can be re-run under a debugger. There's
+//     no time budget when replaying. With --dump that turn's map is printed to
+//     stderr in the scenario format.
+fn setup_transcript() -> Option<u32> {
+    let args: Vec<String> = env::args().collect();
+    let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    if let Some(path) = arg("--replay") {
+        networking::replay_from(path).expect("can't open transcript");
+        return arg("--turn").map(|n| n.parse().expect("--turn takes a number"))
+    }
+    let default_path = if cfg!(feature = "record") { Some("transcript-{pid}.txt".to_string()) } else { None };
+    if let Some(path) = env::var("A2I_RECORD").ok().or(default_path) {
+        let path = path.replace("{pid}", &process::id().to_string());
+        // Not being able to record is no reason to forfeit the game.
+        if let Err(e) = networking::record_to(&path) {
+            eprintln!("can't create transcript {}, playing without one: {}", path, e);
+        }
+    }
+    None
+}
+
+// Moves every unit the way the policy scores best, leaving validate::repair to
+// keep merges under the cap.
+fn policy_strategy(map: &GameMap, my_id: u8, trace: &mut trace::TurnTrace, budget: &Budget, policy: &Policy) -> MoveSet {
+    use trace::Reason::*;
+    let mut moves = MoveSet::new();
+    for l in get_units_of_player(my_id, map) {
+        if budget.exhausted() {
+            break
+        }
+        trace.unit(l, "Policy");
+        trace.reason(l, Branch("Policy.best"));
+        moves.insert(l, policy.best_move(map, l, my_id));
+    }
+    moves
+}
+
+// Everything worked out or loaded in the init window, fixed for the game.
+struct Setup {
+    statics: MapStatics,
+    policy: Option<Policy>,
+    weights: Weights,
+}
+
+#[derive(Copy, Clone)]
+enum Strategy {
+    MaxCapture,
+    Troop,
+    Policy,
+}
+
+impl Strategy {
+    fn name(self) -> &'static str {
+        match self {
+            Strategy::MaxCapture => "max_capture",
+            Strategy::Troop => "troop",
+            Strategy::Policy => "policy",
+        }
+    }
+
+    fn run(self, map: &GameMap, my_id: u8, trace: &mut trace::TurnTrace, budget: &Budget, setup: &Setup, state: &mut BotState) -> MoveSet {
+        match self {
+            Strategy::MaxCapture => max_capture_strategy(map, my_id, trace, budget),
+            Strategy::Troop => troop_strategy(map, my_id, trace, budget, &setup.statics, &setup.weights, &mut state.schedule),
+            Strategy::Policy => policy_strategy(map, my_id, trace, budget, setup.policy.as_ref().expect("no policy loaded")),
+        }
+    }
+
+    // What to try instead when this strategy panics. After that, everything stays still.
+    fn fallback(self) -> Option<Strategy> {
+        match self {
+            Strategy::MaxCapture => None,
+            Strategy::Troop => Some(Strategy::MaxCapture),
+            Strategy::Policy => Some(Strategy::Troop),
+        }
+    }
+}
+
+fn panic_message(e: &Box<dyn Any + Send>) -> String {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
     }
 }
 
+// Runs the strategy for this turn, falling back to cheaper ones and finally to
+// all STILL if they panic or we run out of time. A panic must never cost us the game.
+fn choose_moves(map: &GameMap, my_id: u8, trace: &mut trace::TurnTrace, budget: &Budget, setup: &Setup, state: &mut BotState) -> MoveSet {
+    let my_count = get_units_of_player(my_id, map).len();
+    let mut strategy = Some(if setup.policy.is_some() {
+        Strategy::Policy
+    } else if my_count < 10 {
+        Strategy::MaxCapture
+    } else {
+        Strategy::Troop
+    });
+    let mut moves = MoveSet::new();
+    while let Some(s) = strategy {
+        trace.strategy(s.name());
+        match panic::catch_unwind(AssertUnwindSafe(|| s.run(map, my_id, trace, budget, setup, state))) {
+            Ok(m) => {
+                if !matches!(s, Strategy::Troop) {
+                    // Plans count turns, so they only survive unbroken runs of troop_strategy.
+                    state.schedule.clear();
+                }
+                moves = m;
+                break
+            },
+            Err(e) => {
+                state.schedule.clear();
+                trace.panic(s.name(), panic_message(&e), map);
+                strategy = if budget.nearly_exhausted() { None } else { s.fallback() };
+            },
+        }
+    }
+    match panic::catch_unwind(AssertUnwindSafe(|| validate::repair(map, my_id, &mut moves, budget))) {
+        Ok(repairs) => trace.repairs(&repairs),
+        Err(e) => {
+            trace.panic("repair", panic_message(&e), map);
+            moves.clear();
+        },
+    }
+    state.schedule.confirm(map, &moves);
+    moves
+}
+
+// An offline tool's entry point, given the arguments after its flag.
+type Tool = fn(&[String]) -> Result<(), String>;
+
 fn main() {
+    // Offline tools that the engine never asks for.
+    let args: Vec<String> = env::args().collect();
+    let tool: Option<Tool> = match args.get(1).map(|a| a.as_str()) {
+        Some("--mine") => Some(mine::run),
+        Some("--export") => Some(export::run),
+        _ => None,
+    };
+    if let Some(run) = tool {
+        if let Err(e) = run(&args[2..]) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return
+    }
+    let stop_after = setup_transcript();
     let (my_id, mut game_map) = networking::get_init();
-    networking::send_init(format!("{}{}", "Asp2Insp".to_string(), my_id.to_string()));
+    // The init window is much longer than a turn, so do the static work now.
+    let setup = Setup {
+        statics: MapStatics::new(&game_map, my_id),
+        policy: Policy::load(),
+        weights: Weights::load(),
+    };
+    networking::send_init(format!("{}{}", "Asp2Insp", my_id));
+    let mut tracer = trace::Tracer::open(my_id);
+    tracer.statics(&setup.statics);
+    let mut state = BotState::new(&game_map, my_id);
+    let mut game_waste = waste::GameWaste::default();
+    // A replayed turn has to decide the same way however long it sits in a debugger.
+    let limit = if env::args().any(|a| a == "--replay") { Budget::unlimited() } else { Budget::turn_limit() };
     loop {
-        networking::get_frame(&mut game_map);
-        let my_count = get_units_of_player(my_id, &game_map).len();
-        let moves = if my_count < 10 {
-            max_capture_strategy(&game_map, my_id)
-        } else {
-            troop_strategy(&game_map, my_id)
-        };
-        networking::send_frame(moves);
+        if networking::input_exhausted() {
+            break
+        }
+        // The engine's clock is already running once the frame starts arriving.
+        let budget = Budget::start(limit);
+        let frame = panic::catch_unwind(AssertUnwindSafe(|| networking::get_frame(&mut game_map)));
+        if let Err(e) = frame {
+            // Whatever we parsed can't be trusted, so stay still and hope the next frame is better.
+            state.lost_frame();
+            let mut t = tracer.begin_turn(state.turn);
+            t.panic("get_frame", panic_message(&e), &game_map);
+            tracer.end_turn(t, &MoveSet::new());
+            networking::send_frame(MoveSet::new(), false);
+            continue
+        }
+        state.begin_turn(&game_map);
+        let mut t = tracer.begin_turn(state.turn);
+        // Only the trace reports waste, so submission builds don't measure it.
+        if cfg!(feature = "trace") {
+            if let Some((prev_map, prev_moves)) = state.previous() {
+                let turn_waste = waste::measure(prev_map, prev_moves, &game_map, my_id);
+                game_waste.record(&turn_waste);
+                t.waste(&turn_waste);
+                tracer.summary(&game_waste, &state, &game_map);
+            }
+        }
+        let moves = choose_moves(&game_map, my_id, &mut t, &budget, &setup, &mut state);
+        state.end_turn(&game_map, &moves);
+        tracer.end_turn(t, &moves);
+        networking::send_frame(moves, false);
+        if stop_after == Some(state.turn) {
+            if env::args().any(|a| a == "--dump") {
+                eprint!("{}", game_map);
+            }
+            break
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use testmaps::generated_map;
+
+    // Runs a strategy twice on each generated map, with fresh state both times,
+    // and checks that it picks the same moves.
+    fn assert_deterministic<F>(strategy: F)
+        where F: Fn(&GameMap, &mut trace::TurnTrace, &Budget, &MapStatics) -> MoveSet {
+        for seed in 0..50 {
+            let map = generated_map(seed);
+            let statics = MapStatics::new(&map, 1);
+            let budget = Budget::start(Duration::from_secs(60));
+            let first = strategy(&map, &mut trace::TurnTrace::new(0), &budget, &statics);
+            let second = strategy(&map, &mut trace::TurnTrace::new(0), &budget, &statics);
+            assert_eq!(first, second, "different moves for map {}", seed);
+        }
+    }
+
+    #[test]
+    fn classify_loc_finds_a_pincer_in_a_scenario() {
+        let map = scenario::parse("
+            .    .    .    .    .
+            .    1:50 2:60 .    .
+            .    1:50 1:50 .    .
+            .    .    1:50 .    .
+            .    .    .    .    .
+        ").unwrap();
+        match classify_loc(Location { x: 2, y: 2 }, &map, 1) {
+            Troop::Pincer(l1, l2, e) => {
+                assert_eq!((l1, l2, e), (Location { x: 1, y: 1 }, Location { x: 2, y: 2 }, Location { x: 2, y: 1 }));
+            },
+            t => panic!("expected a Pincer, got {}", t.name()),
+        }
+    }
+
+    #[test]
+    fn default_weights_pick_what_the_filters_did() {
+        let weights = Weights::default();
+        for seed in 0..20 {
+            let map = generated_map(seed);
+            for l in get_units_of_player(1, &map) {
+                // Throw out what a filter rejects, then take the weakest target and
+                // then the most productive, the later direction on ties.
+                let mut kept = candidates(l, &map, 1).into_iter().skip(1).filter(|a| rejected_by(a).is_none()).collect::<Vec<_>>();
+                kept.sort_by_key(|a| a.production_them);
+                kept.sort_by_key(|a| -a.strength_them);
+                let filtered = kept.pop().map_or(STILL, |a| a.d);
+                assert_eq!(get_best_move_simple(l, &map, 1, &weights, &mut trace::TurnTrace::new(0)), filtered, "map {} at {:?}", seed, l);
+            }
+        }
+    }
+
+    #[test]
+    fn max_capture_strategy_is_deterministic() {
+        assert_deterministic(|map, trace, budget, _| max_capture_strategy(map, 1, trace, budget));
+    }
+
+    #[test]
+    fn troop_strategy_is_deterministic() {
+        assert_deterministic(|map, trace, budget, statics| troop_strategy(map, 1, trace, budget, statics, &Weights::default(), &mut Schedule::default()));
+    }
+
+    #[test]
+    fn troop_strategy_follows_routes_through_units_moving_on() {
+        // The 200 can only join the 100 because the 100 is routed on east.
+        let map = scenario::parse("
+            1:0 1:0   1:0   1:0  0:255 0:255 0:255
+            1:0 1:0   1:0   1:0  0:255 0:255 0:255
+            1:0 1:200 1:100 1:10 0:150 0:255 0:255
+            1:0 1:0   1:0   1:0  0:255 0:255 0:255
+            1:0 1:0   1:0   1:0  0:255 0:255 0:255
+        ").unwrap();
+        let statics = MapStatics::new(&map, 1);
+        let budget = Budget::start(Duration::from_secs(60));
+        let moves = troop_strategy(&map, 1, &mut trace::TurnTrace::new(0), &budget, &statics, &Weights::default(), &mut Schedule::default());
+        assert_eq!((moves[&Location { x: 1, y: 2 }], moves[&Location { x: 2, y: 2 }]), (EAST, EAST));
+    }
+
+    #[test]
+    fn troop_strategy_corners_keep_under_the_cap() {
+        // Both ends of a corner would otherwise merge into the 200 at (1, 1).
+        let map = scenario::parse("
+            0:250 0:250 0:250 0:250 0:250 0:250
+            0:250 1:200 1:200 1:200 1:200 0:250
+            0:250 1:200 1:200 1:200 1:200 0:250
+            0:250 0:250 0:250 0:250 0:250 0:250
+        ").unwrap();
+        let statics = MapStatics::new(&map, 1);
+        let budget = Budget::start(Duration::from_secs(60));
+        let moves = troop_strategy(&map, 1, &mut trace::TurnTrace::new(0), &budget, &statics, &Weights::default(), &mut Schedule::default());
+        assert_eq!(sim::simulate(&map, 1, &moves).wasted, 0, "{:?}", moves);
+    }
+
+    #[test]
+    fn troop_strategy_out_of_time_takes_simple_moves() {
+        let weights = Weights::default();
+        for seed in 0..20 {
+            let map = generated_map(seed);
+            let statics = MapStatics::new(&map, 1);
+            let budget = Budget::start(Duration::from_secs(0));
+            let moves = troop_strategy(&map, 1, &mut trace::TurnTrace::new(0), &budget, &statics, &weights, &mut Schedule::default());
+            let units = get_units_of_player(1, &map);
+            assert_eq!(moves.len(), units.len(), "map {}", seed);
+            for l in units {
+                // commit_move can still hold a unit to stay under the cap.
+                let simple = get_best_move_simple(l, &map, 1, &weights, &mut trace::TurnTrace::new(0));
+                assert!(moves[&l] == simple || moves[&l] == STILL, "map {} at {:?}", seed, l);
+            }
+        }
+    }
+
+    #[test]
+    fn choose_moves_is_deterministic() {
+        assert_deterministic(|map, trace, budget, _| {
+            let setup = Setup { statics: MapStatics::new(map, 1), policy: None, weights: Weights::default() };
+            choose_moves(map, 1, trace, budget, &setup, &mut BotState::new(map, 1))
+        });
     }
 }
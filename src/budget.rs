@@ -0,0 +1,47 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+// Halite gives each bot one second per turn, measured by the engine from when
+// it sends the frame, so we only plan to use part of it.
+const TURN_LIMIT_MS: u64 = 1000;
+const SAFE_FRACTION: f64 = 0.75;
+// Past this share of the budget strategies should switch to their cheapest moves.
+const EMERGENCY_FRACTION: f64 = 0.85;
+
+// Time left for the current turn, for strategies to poll while they work.
+pub struct Budget {
+    start: Instant,
+    limit: Duration,
+}
+
+impl Budget {
+    pub fn start(limit: Duration) -> Budget {
+        Budget { start: Instant::now(), limit }
+    }
+
+    // The per-turn budget, A2I_BUDGET_MS if set, otherwise a safe fraction of the turn limit.
+    pub fn turn_limit() -> Duration {
+        let ms = env::var("A2I_BUDGET_MS").ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or((TURN_LIMIT_MS as f64 * SAFE_FRACTION) as u64);
+        Duration::from_millis(ms)
+    }
+
+    // Never runs out, for replays that must decide the same way every run.
+    pub fn unlimited() -> Duration {
+        Duration::MAX
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.elapsed() >= self.limit
+    }
+
+    // Time to stop refining and fall back to get_best_move_simple.
+    pub fn nearly_exhausted(&self) -> bool {
+        self.elapsed().as_secs_f64() >= self.limit.as_secs_f64() * EMERGENCY_FRACTION
+    }
+}
@@ -0,0 +1,159 @@
+// Plans captures that take more than one of our units, for any site on the
+// frontier rather than the few shapes patterns.rs knows.
+//
+// Targets are taken in frontier order, best payback first. For each, the
+// cheapest set of our neighbours whose strength together beats the target's
+// without going over 255 once merged moves in now. Failing that, one neighbour
+// stays to gather its own neighbours onto it and takes the target alone next
+// turn. A unit is only ever in one plan, so overlapping targets don't pull it
+// two ways.
+
+use frontier::Frontier;
+use hlt::types::*;
+
+// Most strength one of our pieces can have, anything merged above it is lost.
+pub const CAP: u16 = 255;
+
+pub struct Plan {
+    pub target: Location,
+    pub moves: Vec<(Location, u8)>,
+    pub now: bool, // Takes the target this turn, rather than gathering for the next
+}
+
+// What the target will have after turns more turns, since enemies produce
+// while they wait and neutral sites don't.
+pub fn predicted(site: &Site, turns: u16) -> u16 {
+    if site.owner == 0 {
+        site.strength as u16
+    } else {
+        (site.strength as u16 + site.production as u16 * turns).min(CAP)
+    }
+}
+
+// The subset of strengths with the least total that is above need and at
+// most CAP, as a bit mask.
+fn cheapest(strengths: &[u16], base: u16, need: u16) -> Option<u32> {
+    (1..1u32 << strengths.len())
+        .map(|mask| (mask, base + (0..strengths.len()).filter(|i| mask & 1 << i != 0).map(|i| strengths[i]).sum::<u16>()))
+        .filter(|&(_, total)| total > need && total <= CAP)
+        .min_by_key(|&(mask, total)| (total, mask.count_ones(), mask))
+        .map(|(mask, _)| mask)
+}
+
+pub fn plan(map: &GameMap, my_id: u8, frontier: &Frontier) -> Vec<Plan> {
+    let mut used = vec![vec![false; map.width as usize]; map.height as usize];
+    let mut plans = Vec::new();
+    // Our units around l that no plan has taken yet, with the direction from l to each.
+    let free = |used: &Vec<Vec<bool>>, l: Location| CARDINALS.iter().cloned()
+        .map(|d| (d, map.get_location(l, d)))
+        .filter(|&(_, n)| {
+            let site = map.get_site_ref(n, STILL);
+            site.owner == my_id && site.strength > 0 && !used[n.y as usize][n.x as usize]
+        })
+        .collect::<Vec<_>>();
+
+    for t in &frontier.targets {
+        let target = map.get_site_ref(t.loc, STILL);
+        let around = free(&used, t.loc);
+        if around.is_empty() {
+            continue
+        }
+
+        let strengths = around.iter().map(|&(_, n)| map.get_site_ref(n, STILL).strength as u16).collect::<Vec<_>>();
+        let plan = match cheapest(&strengths, 0, predicted(target, 1)) {
+            Some(mask) => Some(Plan {
+                target: t.loc,
+                moves: (0..around.len())
+                    .filter(|i| mask & 1 << i != 0)
+                    .map(|i| (around[i].1, ::reverse(around[i].0)))
+                    .collect(),
+                now: true,
+            }),
+            None => {
+                // A neighbour that stays gets its production as well as whoever joins it.
+                let mut best: Option<(u16, Plan)> = None;
+                for &(_, a) in &around {
+                    let site = map.get_site_ref(a, STILL);
+                    let base = site.strength as u16 + site.production as u16;
+                    let helpers = free(&used, a);
+                    let strengths = helpers.iter().map(|&(_, n)| map.get_site_ref(n, STILL).strength as u16).collect::<Vec<_>>();
+                    if let Some(mask) = cheapest(&strengths, base, predicted(target, 2)) {
+                        let mut moves = vec![(a, STILL)];
+                        moves.extend((0..helpers.len())
+                            .filter(|i| mask & 1 << i != 0)
+                            .map(|i| (helpers[i].1, ::reverse(helpers[i].0))));
+                        let cost = moves.iter().map(|&(l, _)| map.get_site_ref(l, STILL).strength as u16).sum();
+                        if best.as_ref().map_or(true, |b| cost < b.0) {
+                            best = Some((cost, Plan { target: t.loc, moves, now: false }));
+                        }
+                    }
+                }
+                best.map(|b| b.1)
+            },
+        };
+        if let Some(plan) = plan {
+            for &(l, _) in &plan.moves {
+                used[l.y as usize][l.x as usize] = true;
+            }
+            plans.push(plan);
+        }
+    }
+    plans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+    use statics::MapStatics;
+
+    fn plans(map: &str) -> Vec<Plan> {
+        let map = scenario::parse(map).unwrap();
+        plan(&map, 1, &Frontier::new(&map, 1, &MapStatics::new(&map, 1)))
+    }
+
+    #[test]
+    fn two_units_combine_now() {
+        let plans = plans("
+            0:200 0:200 0:200 0:200 0:200
+            0:200 0:200 1:60  0:200 0:200
+            0:200 1:60  0:100 0:200 0:200
+            0:200 0:200 0:200 0:200 0:200
+        ");
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0].target, Location { x: 2, y: 2 });
+        assert!(plans[0].now);
+        assert_eq!(plans[0].moves, vec![(Location { x: 2, y: 1 }, SOUTH), (Location { x: 1, y: 2 }, EAST)]);
+    }
+
+    #[test]
+    fn gathers_when_neighbours_fall_short() {
+        // The 100 and the 40 can't take it between them, so the 100 stays and
+        // gathers the 50 to go alone next turn.
+        let plans = plans("
+            0:250 0:250 0:250 0:250 0:250
+            0:250 1:50  1:100 0:150 0:250
+            0:250 0:250 0:250 1:40  0:250
+            0:250 0:250 0:250 0:250 0:250
+        ");
+        let p = plans.iter().find(|p| p.target == Location { x: 3, y: 1 }).unwrap();
+        assert!(!p.now);
+        assert_eq!(p.moves, vec![(Location { x: 2, y: 1 }, STILL), (Location { x: 1, y: 1 }, EAST)]);
+    }
+
+    #[test]
+    fn units_join_one_plan() {
+        // Both targets want the same helpers, but each unit goes one way.
+        let plans = plans("
+            0:250 0:250 0:250 0:250 0:250 0:250
+            0:250 0:70  1:40  1:40  0:70  0:250
+            0:250 0:250 1:40  1:40  0:250 0:250
+            0:250 0:250 0:250 0:250 0:250 0:250
+        ");
+        let mut moved = plans.iter().flat_map(|p| p.moves.iter().map(|m| m.0)).collect::<Vec<_>>();
+        let count = moved.len();
+        moved.sort();
+        moved.dedup();
+        assert_eq!(moved.len(), count);
+    }
+}
@@ -0,0 +1,144 @@
+// Runs classify_loc over every one of the 256 ways the eight neighbours can be
+// friendly or not, checking that troops are made of friendly units aimed at
+// unfriendly targets, that turning the board turns the troop with it, and
+// reporting any arms, pattern variants or Unknown, that can never be reached.
+//
+// cargo test classify_loc -- --nocapture prints the unreachable arms.
+
+use super::*;
+
+// Centre of the 5x5 test board, far enough from the edges that nothing wraps.
+const CENTRE: Location = Location { x: 2, y: 2 };
+
+// Neighbour offsets in bit order: nw nn ne ww ee sw ss se.
+const NEIGHBOURS: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+
+fn at(offset: (i32, i32)) -> Location {
+    Location { x: (CENTRE.x as i32 + offset.0) as u16, y: (CENTRE.y as i32 + offset.1) as u16 }
+}
+
+fn offset(l: Location) -> (i32, i32) {
+    (l.x as i32 - CENTRE.x as i32, l.y as i32 - CENTRE.y as i32)
+}
+
+// A quarter turn clockwise, with y pointing south.
+fn rotate(o: (i32, i32)) -> (i32, i32) {
+    (-o.1, o.0)
+}
+
+fn rotate_pattern(pattern: u8) -> u8 {
+    (0..8).filter(|i| pattern & (1 << i) != 0)
+        .map(|i| 1 << NEIGHBOURS.iter().position(|n| *n == rotate(NEIGHBOURS[i])).unwrap())
+        .fold(0, |p, bit| p | bit)
+}
+
+// The board for a pattern: the centre and the neighbours whose bit is set are
+// ours, everything else is neutral.
+fn board(pattern: u8) -> GameMap {
+    let site = Site { owner: 0, strength: 10, production: 1 };
+    let mut map = GameMap { width: 5, height: 5, contents: vec![vec![site; 5]; 5] };
+    map.get_site(CENTRE, STILL).owner = 1;
+    for (i, n) in NEIGHBOURS.iter().enumerate() {
+        if pattern & (1 << i) != 0 {
+            map.get_site(at(*n), STILL).owner = 1;
+        }
+    }
+    map
+}
+
+fn targets(t: &Troop) -> Vec<Location> {
+    match *t {
+        Troop::Pincer(_, _, e) | Troop::Pincer3(_, _, _, e) => vec![e],
+        _ => vec![],
+    }
+}
+
+// What a troop looks like after the board is turned a quarter clockwise.
+fn rotated_name(name: &'static str) -> &'static str {
+    match name {
+        "VerticalWall" => "HorizontalWall",
+        "HorizontalWall" => "VerticalWall",
+        n => n,
+    }
+}
+
+type Arm = (&'static str, Vec<(i32, i32)>, Vec<(i32, i32)>);
+
+// Units in a troop can be listed in any order, so arms are compared with their
+// members sorted.
+fn normalised(name: &'static str, mut members: Vec<(i32, i32)>, targets: Vec<(i32, i32)>) -> Arm {
+    members.sort();
+    (name, members, targets)
+}
+
+// Which arm of classify_loc produced a troop: its kind and where its units and
+// target sit relative to the centre.
+fn arm(t: &Troop) -> Arm {
+    normalised(t.name(), t.members().into_iter().map(offset).collect(), targets(t).into_iter().map(offset).collect())
+}
+
+fn rotate_arm(a: &Arm) -> Arm {
+    normalised(rotated_name(a.0), a.1.iter().cloned().map(rotate).collect(), a.2.iter().cloned().map(rotate).collect())
+}
+
+// Every arm classify_loc has: each variant the pattern table expands to, then
+// Unknown for when none fit.
+fn all_arms() -> Vec<Arm> {
+    let map = board(0);
+    let mut arms = patterns::compile(patterns::PATTERNS).unwrap().iter()
+        .map(|v| arm(&troop(&v.locate(CENTRE, &map), CENTRE)))
+        .collect::<Vec<_>>();
+    arms.push(arm(&Troop::Unknown(CENTRE)));
+    arms
+}
+
+#[test]
+fn classify_loc_troops_are_friendly_and_targets_are_not() {
+    for pattern in 0..=255u8 {
+        let map = board(pattern);
+        let t = classify_loc(CENTRE, &map, 1);
+        for l in t.members() {
+            assert_eq!(map.get_site_ref(l, STILL).owner, 1, "{} for pattern {:08b} uses unfriendly {:?}", t.name(), pattern, offset(l));
+        }
+        for e in targets(&t) {
+            assert_ne!(map.get_site_ref(e, STILL).owner, 1, "{} for pattern {:08b} targets friendly {:?}", t.name(), pattern, offset(e));
+        }
+    }
+}
+
+// Turning the board has to turn the troop's kind with it. Where more than one
+// Pincer or Pincer3 fits, variant order picks one, so the turned board may pick
+// a different one; that is fine as long as the turned troop is still a troop
+// classify_loc could make there. Those ties are printed.
+#[test]
+fn classify_loc_turns_with_the_board() {
+    for pattern in 0..=255u8 {
+        let t = classify_loc(CENTRE, &board(pattern), 1);
+        let turned_board = board(rotate_pattern(pattern));
+        let turned = classify_loc(CENTRE, &turned_board, 1);
+        assert_eq!(turned.name(), rotated_name(t.name()), "pattern {:08b} is a {} but turned a quarter it is a {}", pattern, t.name(), turned.name());
+
+        let expected = rotate_arm(&arm(&t));
+        if arm(&turned) == expected {
+            continue
+        }
+        assert!(all_arms().contains(&expected), "pattern {:08b}: {} {:?} turned is no arm of classify_loc", pattern, expected.0, expected.1);
+        let owner = |o: &(i32, i32)| turned_board.get_site_ref(at(*o), STILL).owner;
+        assert!(expected.1.iter().all(|o| owner(o) == 1) && expected.2.iter().all(|o| owner(o) != 1),
+                "pattern {:08b}: {} turned doesn't fit the turned board", pattern, t.name());
+        println!("tie: pattern {:08b} is a {} of {:?} on {:?}, turned it is one of {:?} on {:?}", pattern, expected.0, expected.1, expected.2, arm(&turned).1, arm(&turned).2);
+    }
+}
+
+#[test]
+fn classify_loc_unreachable_arms() {
+    let reached = (0..=255u8).map(|p| arm(&classify_loc(CENTRE, &board(p), 1))).collect::<Vec<_>>();
+    let dead = all_arms().into_iter().filter(|a| !reached.contains(a)).collect::<Vec<_>>();
+    for a in &dead {
+        println!("unreachable: {} of {:?} on {:?}", a.0, a.1, a.2);
+    }
+    // Anything classify_loc returns has to be one of its arms.
+    for a in &reached {
+        assert!(all_arms().contains(a), "classify_loc returned {} of {:?} on {:?}, which isn't a known arm", a.0, a.1, a.2);
+    }
+}
@@ -0,0 +1,269 @@
+// Attacks timed over several turns: units near a target that can't be taken
+// now wait to grow while the ones further out walk in, so they all reach it in
+// the same turn with enough strength between them. Plans are kept from frame to
+// frame and dropped as soon as they stop adding up, or as soon as a member is
+// sent something other than what its plan gave it.
+//
+// A member leaves once its wait is over and walks straight at the target over
+// our own sites. What it brings is its strength plus the production of every
+// turn it waits, and the group has to beat what the target will have by then
+// without going over 255 once merged.
+
+use capture::{predicted, Plan, CAP};
+use frontier::Frontier;
+use hlt::types::*;
+use statics::MapStatics;
+use std::collections::HashSet;
+
+// Most turns ahead an attack is planned.
+const HORIZON: u16 = 3;
+
+// Farthest a member can start from its target.
+const REACH: i32 = 3;
+
+#[derive(Clone, Debug)]
+struct Member {
+    at: Location, // Where it is this frame
+    wait: u16, // Turns left before it sets off
+    next: u8, // The move step gave it this frame
+}
+
+#[derive(Clone, Debug)]
+struct Convergence {
+    target: Location,
+    members: Vec<Member>,
+    arrive_in: u16, // Turns until the group reaches the target
+}
+
+#[derive(Default)]
+pub struct Schedule {
+    plans: Vec<Convergence>,
+}
+
+// What a unit at l brings to target after waiting wait turns, or None if it
+// can't walk there over our own sites.
+fn brings(map: &GameMap, my_id: u8, l: Location, target: Location, wait: u16) -> Option<u16> {
+    let site = map.get_site_ref(l, STILL);
+    if site.owner != my_id || site.strength == 0 {
+        return None
+    }
+    let mut at = l;
+    loop {
+        at = map.get_location(at, map.get_direction(at, target));
+        if at == target {
+            break
+        }
+        if map.get_site_ref(at, STILL).owner != my_id {
+            return None
+        }
+    }
+    Some((site.strength as u16 + site.production as u16 * wait).min(CAP))
+}
+
+impl Convergence {
+    // Whether the group still beats the target when it gets there.
+    fn holds(&self, map: &GameMap, my_id: u8, busy: &HashSet<Location>) -> bool {
+        let mut total = 0;
+        for m in &self.members {
+            if busy.contains(&m.at) {
+                return false
+            }
+            match brings(map, my_id, m.at, self.target, m.wait) {
+                Some(s) => total += s,
+                None => return false,
+            }
+        }
+        let target = map.get_site_ref(self.target, STILL);
+        target.owner != my_id && total > predicted(target, self.arrive_in) && total <= CAP
+    }
+}
+
+// The soonest attack on target from the units around it, fewest and strongest
+// first, that no one else is using.
+fn schedule(map: &GameMap, my_id: u8, statics: &MapStatics, target: Location, taken: &HashSet<Location>) -> Option<Convergence> {
+    let mut around = Vec::new();
+    for oy in -REACH..REACH + 1 {
+        for ox in -REACH..REACH + 1 {
+            if ox.abs() + oy.abs() == 0 || ox.abs() + oy.abs() > REACH {
+                continue
+            }
+            let l = map.get_offset(target, ox, oy);
+            // Small maps wrap onto the same site more than once.
+            if !taken.contains(&l) && !around.contains(&l) {
+                around.push(l);
+            }
+        }
+    }
+    let need_at = |turns| predicted(map.get_site_ref(target, STILL), turns);
+    for arrive_in in 1..HORIZON + 1 {
+        let mut offers = around.iter()
+            .filter_map(|l| {
+                let d = statics.distance(*l, target);
+                if d > arrive_in {
+                    return None
+                }
+                brings(map, my_id, *l, target, arrive_in - d).map(|s| (s, *l, arrive_in - d))
+            })
+            .collect::<Vec<_>>();
+        offers.sort_by(|a, b| b.0.cmp(&a.0).then((a.1.y, a.1.x).cmp(&(b.1.y, b.1.x))));
+        let need = need_at(arrive_in);
+        let (mut total, mut members) = (0, Vec::new());
+        for (s, at, wait) in offers {
+            if total > need {
+                break
+            }
+            total += s;
+            members.push(Member { at, wait, next: STILL });
+        }
+        // One unit on its own is just waiting, which the troops do already.
+        if total > need && total <= CAP && members.len() > 1 {
+            return Some(Convergence { target, members, arrive_in })
+        }
+    }
+    None
+}
+
+impl Schedule {
+    pub fn clear(&mut self) {
+        self.plans.clear();
+    }
+
+    // Drops plans that no longer add up, plans attacks on frontier targets that
+    // nothing else is taking, and gives this turn's move for every member.
+    // Units in captures are left alone.
+    pub fn step(&mut self, map: &GameMap, my_id: u8, frontier: &Frontier, statics: &MapStatics, captures: &[Plan]) -> Vec<(Location, u8)> {
+        let mut busy = captures.iter().flat_map(|p| p.moves.iter().map(|m| m.0)).collect::<HashSet<_>>();
+        let mut targets = captures.iter().map(|p| p.target).collect::<HashSet<_>>();
+        self.plans.retain(|c| c.holds(map, my_id, &busy) && !targets.contains(&c.target));
+        for c in &self.plans {
+            targets.insert(c.target);
+            busy.extend(c.members.iter().map(|m| m.at));
+        }
+        for t in &frontier.targets {
+            if targets.contains(&t.loc) {
+                continue
+            }
+            if let Some(c) = schedule(map, my_id, statics, t.loc, &busy) {
+                busy.extend(c.members.iter().map(|m| m.at));
+                targets.insert(t.loc);
+                self.plans.push(c);
+            }
+        }
+
+        let mut moves = Vec::new();
+        for c in &mut self.plans {
+            for m in &mut c.members {
+                m.next = if m.wait > 0 { STILL } else { map.get_direction(m.at, c.target) };
+                moves.push((m.at, m.next));
+            }
+        }
+        moves
+    }
+
+    // Moves plans on by what was actually sent this turn. Plans with a member
+    // that was sent something else, by commit_move, the budget or
+    // validate::repair, are dropped since their timing no longer holds.
+    pub fn confirm(&mut self, map: &GameMap, sent: &MoveSet) {
+        self.plans.retain(|c| c.members.iter().all(|m| *sent.get(&m.at).unwrap_or(&STILL) == m.next));
+        for c in &mut self.plans {
+            for m in &mut c.members {
+                if m.next == STILL {
+                    m.wait -= 1;
+                } else {
+                    m.at = map.get_location(m.at, m.next);
+                }
+            }
+            c.arrive_in -= 1;
+        }
+        self.plans.retain(|c| c.arrive_in > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+    use sim;
+
+    #[test]
+    fn waits_then_arrives_together() {
+        // 50 and 30 can't take the 100 now, but after two turns of production they can.
+        let mut map = scenario::parse("
+            0:250 0:250 0:250 0:250 0:250
+            0:250 1:50  0:100 1:30  0:250
+            0:250 0:250 0:250 0:250 0:250
+
+            1 1  1 1  1
+            1 10 1 10 1
+            1 1  1 1  1
+        ").unwrap();
+        let statics = MapStatics::new(&map, 1);
+        let mut schedule = Schedule::default();
+        for turn in 0..3 {
+            let moves = schedule.step(&map, 1, &Frontier::new(&map, 1, &statics), &statics, &[]);
+            let still = moves.iter().filter(|m| m.1 == STILL).count();
+            assert_eq!((moves.len(), still), (2, if turn < 2 { 2 } else { 0 }), "turn {}", turn);
+            let moves = moves.into_iter().collect();
+            schedule.confirm(&map, &moves);
+            map = sim::simulate(&map, 1, &moves).map;
+        }
+        assert_eq!(map.get_site_ref(Location { x: 2, y: 1 }, STILL).owner, 1);
+        assert!(schedule.plans.is_empty());
+    }
+
+    #[test]
+    fn far_members_leave_early() {
+        // The 60 is two away, so it sets off while the 40 next to the target waits.
+        let map = scenario::parse("
+            0:250 0:250 0:250 0:250 0:250
+            0:250 1:60  1:0   0:90  1:40
+            0:250 0:250 0:250 0:250 0:250
+        ").unwrap();
+        let statics = MapStatics::new(&map, 1);
+        let mut schedule = Schedule::default();
+        let moves = schedule.step(&map, 1, &Frontier::new(&map, 1, &statics), &statics, &[]);
+        assert!(moves.contains(&(Location { x: 1, y: 1 }, EAST)), "{:?}", moves);
+        assert!(moves.contains(&(Location { x: 4, y: 1 }, STILL)), "{:?}", moves);
+    }
+
+    #[test]
+    fn plans_drop_when_a_move_is_changed() {
+        let map = scenario::parse("
+            0:250 0:250 0:250 0:250 0:250
+            0:250 1:60  1:0   0:90  1:40
+            0:250 0:250 0:250 0:250 0:250
+        ").unwrap();
+        let statics = MapStatics::new(&map, 1);
+        let mut schedule = Schedule::default();
+        let moves = schedule.step(&map, 1, &Frontier::new(&map, 1, &statics), &statics, &[]);
+        schedule.confirm(&map, &moves.into_iter().collect());
+        assert_eq!(schedule.plans.len(), 1);
+
+        // Next turn the 60 is held on its way in, so it would arrive late.
+        let map = sim::simulate(&map, 1, &vec![(Location { x: 1, y: 1 }, EAST)].into_iter().collect()).map;
+        let mut moves = schedule.step(&map, 1, &Frontier::new(&map, 1, &statics), &statics, &[]).into_iter().collect::<MoveSet>();
+        assert_eq!(moves[&Location { x: 2, y: 1 }], EAST);
+        moves.insert(Location { x: 2, y: 1 }, STILL);
+        schedule.confirm(&map, &moves);
+        assert!(schedule.plans.is_empty());
+    }
+
+    #[test]
+    fn plans_give_way_to_captures() {
+        let map = scenario::parse("
+            0:250 0:250 0:250 0:250 0:250
+            0:250 1:50  0:100 1:30  0:250
+            0:250 0:250 0:250 0:250 0:250
+
+            1 1  1 1  1
+            1 10 1 10 1
+            1 1  1 1  1
+        ").unwrap();
+        let statics = MapStatics::new(&map, 1);
+        let frontier = Frontier::new(&map, 1, &statics);
+        let mut schedule = Schedule::default();
+        assert!(!schedule.step(&map, 1, &frontier, &statics, &[]).is_empty());
+        let capture = Plan { target: Location { x: 2, y: 1 }, moves: vec![(Location { x: 1, y: 1 }, EAST)], now: true };
+        assert!(schedule.step(&map, 1, &frontier, &statics, &[capture]).is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+// Writes training data for move prediction from .hlt replays, which local games
+// leave behind as well: one row per owned site per turn.
+//
+//   MyBot --export [--size <n>] [--player <name>] [--format csv|bin] [--out <path>] <replay.hlt>...
+//
+// A row is the replay's index among the arguments, the turn, the player, the
+// player's final rank and whether it won, the move the site made, then the
+// window::features of the size x size window around it (5 unless told
+// otherwise). Windows are in canonical orientation and the move is turned to
+// match, so a policy fitted on the rows predicts moves in that orientation.
+// Without --player every player is exported. Rows go to stdout unless --out is given.
+//
+// The binary format is little endian: "A2IT", then u32 version 1, u32 size and
+// u32 channels, then for every row u16 game, u16 turn, u8 player, u8 rank,
+// u8 won, u8 move and size * size * channels f32 features.
+
+use super::*;
+use replay::Replay;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Csv,
+    Binary,
+}
+
+struct Options {
+    size: i32,
+    player: Option<String>,
+    format: Format,
+    out: Option<String>,
+    paths: Vec<String>,
+}
+
+const USAGE: &str = "usage: MyBot --export [--size <n>] [--player <name>] [--format csv|bin] [--out <path>] <replay.hlt>...";
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { size: 5, player: None, format: Format::Csv, out: None, paths: Vec::new() };
+    let mut args = args.iter();
+    while let Some(a) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} takes a value", name));
+        match a.as_str() {
+            "--size" => options.size = match value(a)?.parse() {
+                Ok(n) if n % 2 == 1 => n,
+                _ => return Err("--size takes an odd number".to_string()),
+            },
+            "--player" => options.player = Some(value(a)?),
+            "--format" => options.format = match value(a)?.as_str() {
+                "csv" => Format::Csv,
+                "bin" => Format::Binary,
+                f => return Err(format!("--format is csv or bin, not {}", f)),
+            },
+            "--out" => options.out = Some(value(a)?),
+            _ => options.paths.push(a.clone()),
+        }
+    }
+    if options.paths.is_empty() {
+        return Err(USAGE.to_string())
+    }
+    Ok(options)
+}
+
+fn header(out: &mut dyn Write, size: i32, format: Format) -> io::Result<()> {
+    match format {
+        Format::Csv => {
+            write!(out, "game,turn,player,rank,won,move")?;
+            for i in 0..size * size {
+                for channel in &["ours", "enemy", "strength", "production"] {
+                    write!(out, ",{}_{}", channel, i)?;
+                }
+            }
+            writeln!(out)
+        },
+        Format::Binary => {
+            out.write_all(b"A2IT")?;
+            for n in &[1u32, size as u32, window::CHANNELS as u32] {
+                out.write_all(&n.to_le_bytes())?;
+            }
+            Ok(())
+        },
+    }
+}
+
+struct Row<'a> {
+    game: u16,
+    turn: u16,
+    player: u8,
+    rank: u8,
+    moved: u8,
+    features: &'a [f32],
+}
+
+fn write_row(out: &mut dyn Write, row: &Row, format: Format) -> io::Result<()> {
+    let won = (row.rank == 1) as u8;
+    match format {
+        Format::Csv => {
+            write!(out, "{},{},{},{},{},{}", row.game, row.turn, row.player, row.rank, won, row.moved)?;
+            for f in row.features {
+                write!(out, ",{}", f)?;
+            }
+            writeln!(out)
+        },
+        Format::Binary => {
+            out.write_all(&row.game.to_le_bytes())?;
+            out.write_all(&row.turn.to_le_bytes())?;
+            out.write_all(&[row.player, row.rank, won, row.moved])?;
+            for f in row.features {
+                out.write_all(&f.to_le_bytes())?;
+            }
+            Ok(())
+        },
+    }
+}
+
+// Writes the rows for one replay and returns how many there were.
+fn export_replay(out: &mut dyn Write, game: u16, replay: &Replay, options: &Options) -> io::Result<u32> {
+    let ranks = replay.ranks();
+    let players = (1..replay.player_names.len() as u8 + 1)
+        .filter(|p| options.player.as_ref().map_or(true, |name| replay.player_names[*p as usize - 1].contains(name.as_str())))
+        .collect::<Vec<_>>();
+    let mut rows = 0;
+    for (turn, (map, moved)) in replay.frames.iter().zip(&replay.moves).enumerate() {
+        for &player in &players {
+            for l in get_units_of_player(player, map) {
+                let (o, features) = window::features(map, l, player, options.size);
+                let row = Row {
+                    game,
+                    turn: turn as u16,
+                    player,
+                    rank: ranks[player as usize - 1],
+                    moved: o.turn(moved[l.y as usize][l.x as usize]),
+                    features: &features,
+                };
+                write_row(out, &row, options.format)?;
+                rows += 1;
+            }
+        }
+    }
+    Ok(rows)
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let mut out: BufWriter<Box<dyn Write>> = BufWriter::new(match options.out {
+        Some(ref path) => Box::new(File::create(path).map_err(|e| format!("can't create {}: {}", path, e))?),
+        None => Box::new(io::stdout()),
+    });
+    let write_error = |e: io::Error| format!("can't write rows: {}", e);
+    header(&mut out, options.size, options.format).map_err(write_error)?;
+    let mut rows = 0;
+    for (game, path) in options.paths.iter().enumerate() {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        let replay = Replay::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+        rows += export_replay(&mut out, game as u16, &replay, &options).map_err(write_error)?;
+    }
+    out.flush().map_err(write_error)?;
+    eprintln!("{} rows from {} replays", rows, options.paths.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TINY: &str = r#"{"width": 3, "height": 1, "num_players": 2,
+        "player_names": ["a", "b"],
+        "productions": [[3, 1, 2]],
+        "frames": [[[[1, 10], [0, 5], [2, 5]]], [[[1, 0], [1, 5], [2, 6]]]],
+        "moves": [[[2, 0, 0]]]}"#;
+
+    fn export(format: Format) -> Vec<u8> {
+        let replay = Replay::parse(TINY).unwrap();
+        let options = Options { size: 3, player: None, format, out: None, paths: Vec::new() };
+        let mut out = Vec::new();
+        header(&mut out, options.size, format).unwrap();
+        assert_eq!(export_replay(&mut out, 0, &replay, &options).unwrap(), 2);
+        out
+    }
+
+    #[test]
+    fn csv_has_a_row_per_owned_site() {
+        let csv = String::from_utf8(export(Format::Csv)).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        let columns = 6 + 9 * window::CHANNELS;
+        assert!(lines.iter().all(|l| l.split(',').count() == columns), "{}", csv);
+        assert!(lines[1].starts_with("0,0,1,1,1,"), "{}", lines[1]);
+        assert!(lines[2].starts_with("0,0,2,2,0,0,"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn binary_rows_are_fixed_size() {
+        let bin = export(Format::Binary);
+        assert_eq!(&bin[..4], b"A2IT");
+        assert_eq!(bin.len(), 16 + 2 * (8 + 9 * window::CHANNELS * 4));
+    }
+}
@@ -0,0 +1,139 @@
+// Values the sites we don't own yet by how soon taking them pays for itself,
+// so interior units and attackers go for cheap production before strong or
+// barren sites.
+//
+// A site's payback is the strength it takes to capture over the production it
+// gives each turn, with half the cluster production around it from MapStatics
+// added in so a site in a productive cluster beats a lone one. Sites further out
+// than the frontier also pay for the turns it takes our border to reach them,
+// and sites an enemy started nearer to than we did for the fight over them.
+
+use hlt::types::*;
+use statics::MapStatics;
+use std::collections::VecDeque;
+
+// How many sites out from our territory we look.
+const NEAR_FRONTIER: u16 = 3;
+
+// How much the cluster's production counts next to the site's own.
+const SMOOTHING: f32 = 0.5;
+
+// Turns added to the payback of a contested site.
+const CONTESTED: f32 = 5.0;
+
+// Keeps barren sites from dividing by zero, they just pay back very late.
+const MIN_GAIN: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Target {
+    pub loc: Location,
+    pub payback: f32, // Turns until capturing pays for itself, lower is better
+}
+
+pub struct Frontier {
+    pub targets: Vec<Target>, // Best first
+    payback: Vec<Vec<f32>>, // By [y][x], infinite for sites that aren't targets
+}
+
+// Production a site gives, smoothed with the cluster around it.
+fn gain(map: &GameMap, statics: &MapStatics, l: Location) -> f32 {
+    let site = map.get_site_ref(l, STILL);
+    (site.production as f32 + SMOOTHING * statics.cluster_production(l)).max(MIN_GAIN)
+}
+
+impl Frontier {
+    pub fn new(map: &GameMap, my_id: u8, statics: &MapStatics) -> Frontier {
+        // Distance from our territory by breadth first search out from every site we own.
+        let mut distance = vec![vec![u16::MAX; map.width as usize]; map.height as usize];
+        let mut queue = VecDeque::new();
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let l = Location { x, y };
+                if map.get_site_ref(l, STILL).owner == my_id {
+                    distance[y as usize][x as usize] = 0;
+                    queue.push_back(l);
+                }
+            }
+        }
+        let mut targets = Vec::new();
+        while let Some(l) = queue.pop_front() {
+            let d = distance[l.y as usize][l.x as usize];
+            if d > 0 {
+                let site = map.get_site_ref(l, STILL);
+                targets.push(Target {
+                    loc: l,
+                    payback: site.strength as f32 / gain(map, statics, l) + (d - 1) as f32
+                        + if statics.contested(l) { CONTESTED } else { 0.0 },
+                });
+            }
+            if d == NEAR_FRONTIER {
+                continue
+            }
+            for dir in &CARDINALS {
+                let n = map.get_location(l, *dir);
+                if distance[n.y as usize][n.x as usize] == u16::MAX {
+                    distance[n.y as usize][n.x as usize] = d + 1;
+                    queue.push_back(n);
+                }
+            }
+        }
+        targets.sort_by(|a, b| a.payback.partial_cmp(&b.payback).unwrap()
+            .then((a.loc.y, a.loc.x).cmp(&(b.loc.y, b.loc.x))));
+
+        let mut payback = vec![vec![f32::INFINITY; map.width as usize]; map.height as usize];
+        for t in &targets {
+            payback[t.loc.y as usize][t.loc.x as usize] = t.payback;
+        }
+        Frontier { targets, payback }
+    }
+
+    pub fn payback(&self, l: Location) -> f32 {
+        self.payback[l.y as usize][l.x as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+
+    #[test]
+    fn cheap_productive_sites_rank_first() {
+        let map = scenario::parse("
+            0:40 0:40 0:40  0:40 0:40
+            0:40 0:40 0:200 0:40 0:40
+            0:40 0:40 1:50  0:40 0:40
+            0:40 0:40 0:40  0:40 0:40
+            0:40 0:40 0:40  0:40 0:40
+
+            1 1 1 1 1
+            1 1 4 1 1
+            1 4 1 4 4
+            1 1 1 4 1
+            1 1 1 1 1
+        ").unwrap();
+        let frontier = Frontier::new(&map, 1, &MapStatics::new(&map, 1));
+        let at = |x, y| Location { x, y };
+        assert_eq!(frontier.payback(at(2, 2)), f32::INFINITY);
+        assert_eq!(frontier.payback(at(0, 0)), f32::INFINITY); // Too far out
+
+        // Same strength and production, but (3, 2) sits in a cluster.
+        assert!(frontier.payback(at(3, 2)) < frontier.payback(at(1, 2)));
+        // Productive, but strong enough to pay back late.
+        assert!(frontier.payback(at(1, 2)) < frontier.payback(at(2, 1)));
+        // Out past the frontier, so the walk counts.
+        assert!(frontier.payback(at(4, 1)) > 2.0 + 40.0 / 2.0);
+        assert_eq!(frontier.targets[0].loc, at(3, 2));
+    }
+
+    #[test]
+    fn contested_sites_pay_back_later() {
+        // Both three out from us, but player 2 started next to the one to the east.
+        let map = scenario::parse("
+            0:20 0:20 1:50 0:20 0:20 0:20 2:50 0:20 0:20 0:20 0:20
+        ").unwrap();
+        let frontier = Frontier::new(&map, 1, &MapStatics::new(&map, 1));
+        let at = |x| Location { x, y: 0 };
+        assert_eq!(frontier.payback(at(5)), frontier.payback(at(10)) + CONTESTED);
+    }
+}
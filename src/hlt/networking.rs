@@ -2,20 +2,39 @@
 
 use hlt::types;
 use std::io;
-use std::collections::HashMap;
-use std::io::Write;
+use std::cell::RefCell;
+use std::fs::File;
+use std::fmt::Write as FmtWrite;
+use std::io::{BufRead, BufReader, Write};
 use std::str::FromStr;
 
 //Persistant between moves, that way if the user screws up the map it won't persist.
 static mut _width: u16 = 0;
 static mut _height: u16 = 0;
 
-fn serialize_move_set(moves: HashMap<types::Location, u8>) -> String {
-    let mut s: String = String::new();
-    for (l, d) in moves {
-        s = format!("{}{} {} {} ", s, l.x, l.y, d);
+thread_local! {
+    //Where get_string reads from, stdin unless replaying a transcript.
+    static INPUT: RefCell<Box<dyn BufRead>> = RefCell::new(Box::new(BufReader::new(io::stdin())));
+    //Everything read is copied here when recording.
+    static TRANSCRIPT: RefCell<Option<File>> = RefCell::new(None);
+    //Reused for every frame so reading one doesn't allocate.
+    static FRAME: RefCell<String> = RefCell::new(String::new());
+    //Reused for every move set so sending one doesn't allocate.
+    static MOVES: RefCell<(Vec<(types::Location, u8)>, String)> = RefCell::new((Vec::new(), String::new()));
+}
+
+//Appends the moves to out in row-major order, dropping any that are off the map or not a direction.
+fn serialize_move_set(moves: &types::MoveSet, width: u16, height: u16, send_still: bool,
+                      order: &mut Vec<(types::Location, u8)>, out: &mut String) -> () {
+    order.clear();
+    order.extend(moves.iter()
+        .map(|(l, d)| (*l, *d))
+        .filter(|&(l, d)| l.x < width && l.y < height && d <= types::WEST)
+        .filter(|&(_, d)| send_still || d != types::STILL));
+    order.sort_unstable_by_key(|&(l, _)| (l.y, l.x));
+    for &(l, d) in order.iter() {
+        write!(out, "{} {} {} ", l.x, l.y, d).unwrap();
     }
-    s
 }
 
 fn deserialize_map_size(s: String) -> () {
@@ -44,29 +63,56 @@ fn deserialize_productions(s: String) -> types::GameMap {
     gmp
 }
 
-fn deserialize_map(s: String, gmp: &mut types::GameMap) -> () {
-    let splt: Vec<&str> = s.split(" ").collect();
-    unsafe {
-        let mut counter = 0;
-        let mut owner = 0;
-        let mut loc: usize = 0;
-        for a in 0.._height {
-            for b in 0.._width {
-                if counter == 0 {
-                    counter = u16::from_str(splt[loc]).unwrap();
-                    loc += 1;
-                    owner = u8::from_str(splt[loc]).unwrap();
-                    loc += 1;
-                }
-                gmp.get_site(types::Location { x: b, y: a }, types::STILL).owner = owner;
-                counter -= 1;
-            }
+//Yields the unsigned numbers in a line, reading the bytes in place so nothing is allocated.
+struct Numbers<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Numbers<'a> {
+    fn new(s: &'a str) -> Numbers<'a> {
+        Numbers { bytes: s.as_bytes(), pos: 0 }
+    }
+}
+
+impl<'a> Iterator for Numbers<'a> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        while self.pos < self.bytes.len() && !self.bytes[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+        if self.pos == self.bytes.len() {
+            return None;
+        }
+        let mut n: u16 = 0;
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_digit() {
+            n = n * 10 + (self.bytes[self.pos] - b'0') as u16;
+            self.pos += 1;
         }
-        for a in 0.._height {
-            for b in 0.._width {
-                gmp.get_site(types::Location { x: b, y: a }, types::STILL).strength = u8::from_str(splt[loc]).unwrap();
-                loc += 1;
+        Some(n)
+    }
+}
+
+//Writes the run-length encoded owners and then the strengths straight into the map, row by row.
+fn deserialize_map(s: &str, gmp: &mut types::GameMap) -> () {
+    let mut numbers = Numbers::new(s);
+    let mut next = || numbers.next().expect("frame ended early");
+    let mut counter = 0;
+    let mut owner = 0;
+    for row in gmp.contents.iter_mut() {
+        for site in row.iter_mut() {
+            if counter == 0 {
+                counter = next();
+                owner = next() as u8;
             }
+            site.owner = owner;
+            counter -= 1;
+        }
+    }
+    for row in gmp.contents.iter_mut() {
+        for site in row.iter_mut() {
+            site.strength = next() as u8;
         }
     }
 }
@@ -77,17 +123,47 @@ fn send_string(s: String) -> () {
     io::stdout().flush();
 }
 
+fn read_line_into(buf: &mut String) -> () {
+    buf.clear();
+    INPUT.with(|input| input.borrow_mut().read_line(buf)).unwrap();
+    TRANSCRIPT.with(|t| {
+        if let Some(ref mut f) = *t.borrow_mut() {
+            //A failed write only costs the transcript, never the game.
+            let _ = f.write_all(buf.as_bytes());
+        }
+    });
+}
+
 fn get_string() -> String {
     let mut buf = String::new();
-    io::stdin().read_line(&mut buf).unwrap();
+    read_line_into(&mut buf);
     buf.trim().to_owned()
 }
 
+//Copies every line read from the engine into a transcript file, which replay_from can read back.
+pub fn record_to(path: &str) -> io::Result<()> {
+    let f = File::create(path)?;
+    TRANSCRIPT.with(|t| *t.borrow_mut() = Some(f));
+    Ok(())
+}
+
+//Reads init and frames from a transcript written by record_to instead of stdin.
+pub fn replay_from(path: &str) -> io::Result<()> {
+    let f = File::open(path)?;
+    INPUT.with(|input| *input.borrow_mut() = Box::new(BufReader::new(f)));
+    Ok(())
+}
+
+//True once there is nothing left to read, at the end of a transcript or when the engine hangs up.
+pub fn input_exhausted() -> bool {
+    INPUT.with(|input| input.borrow_mut().fill_buf().map(|b| b.is_empty()).unwrap_or(true))
+}
+
 pub fn get_init() -> (u8, types::GameMap) {
     let playerTag: u8 = u8::from_str(&get_string()).unwrap();
     deserialize_map_size(get_string());
     let mut gmp = deserialize_productions(get_string());
-    deserialize_map(get_string(), &mut gmp);
+    deserialize_map(&get_string(), &mut gmp);
     (playerTag, gmp)
 }
 
@@ -96,9 +172,129 @@ pub fn send_init(name: String) -> () {
 }
 
 pub fn get_frame(gmp: &mut types::GameMap) -> () {
-    deserialize_map(get_string(), gmp);
+    FRAME.with(|frame| {
+        let mut frame = frame.borrow_mut();
+        read_line_into(&mut frame);
+        deserialize_map(&frame, gmp);
+    });
+}
+
+//The engine treats any site without a move as STILL, so send_still is only needed to see every move on the wire.
+pub fn send_frame(moves: types::MoveSet, send_still: bool) -> () {
+    MOVES.with(|buffers| {
+        let (ref mut order, ref mut out) = *buffers.borrow_mut();
+        out.clear();
+        let (width, height) = unsafe { (_width, _height) };
+        serialize_move_set(&moves, width, height, send_still, order, out);
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(out.as_bytes()).unwrap();
+        stdout.write_all(b"\n").unwrap();
+        stdout.flush();
+    });
 }
 
-pub fn send_frame(moves: HashMap<types::Location, u8>) -> () {
-    send_string(serialize_move_set(moves));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Instant;
+
+    //A frame line for a width x height map with one run per row, and its expected owners and strengths.
+    fn frame_line(width: u16, height: u16) -> (String, Vec<(u8, u8)>) {
+        let mut runs = Vec::new();
+        let mut strengths = Vec::new();
+        let mut expected = Vec::new();
+        for y in 0..height {
+            let owner = (y % 3) as u8;
+            runs.push(format!("{} {}", width, owner));
+            for x in 0..width {
+                let strength = ((x * 7 + y * 13) % 256) as u8;
+                strengths.push(strength.to_string());
+                expected.push((owner, strength));
+            }
+        }
+        (format!("{} {}", runs.join(" "), strengths.join(" ")), expected)
+    }
+
+    fn empty_map(width: u16, height: u16) -> types::GameMap {
+        let site = types::Site { owner: 0, strength: 0, production: 1 };
+        types::GameMap { width: width, height: height, contents: vec![vec![site; width as usize]; height as usize] }
+    }
+
+    #[test]
+    fn deserialize_map_fills_rows_in_order() {
+        let (line, expected) = frame_line(7, 5);
+        let mut gmp = empty_map(7, 5);
+        deserialize_map(&line, &mut gmp);
+        let parsed = gmp.contents.iter()
+            .flat_map(|row| row.iter().map(|s| (s.owner, s.strength)))
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, expected);
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("a2i-{}-{}", std::process::id(), name)).to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn transcripts_replay_what_was_recorded() {
+        let (engine, transcript) = (temp_path("engine.txt"), temp_path("transcript.txt"));
+        let (line, expected) = frame_line(3, 2);
+        fs::write(&engine, format!("2\n3 2\n1 2 3 4 5 6\n{}\n{}\n", line, line)).unwrap();
+        assert!(record_to(&temp_path("missing/transcript.txt")).is_err());
+
+        //Play the engine's lines from a file while recording them, then play the recording back.
+        replay_from(&engine).unwrap();
+        record_to(&transcript).unwrap();
+        let mut games = Vec::new();
+        for path in &[&engine, &transcript] {
+            if games.len() == 1 {
+                TRANSCRIPT.with(|t| *t.borrow_mut() = None);
+                replay_from(path).unwrap();
+            }
+            let (id, mut gmp) = get_init();
+            assert!(!input_exhausted());
+            get_frame(&mut gmp);
+            assert!(input_exhausted());
+            games.push((id, gmp.contents));
+        }
+        let sites = games[0].1.iter().flat_map(|row| row.iter().map(|s| (s.owner, s.strength))).collect::<Vec<_>>();
+        assert_eq!((games[0].0, sites), (2, expected));
+        assert_eq!(games[0], games[1]);
+        assert_eq!(fs::read_to_string(&transcript).unwrap(), fs::read_to_string(&engine).unwrap());
+        fs::remove_file(&engine).unwrap();
+        fs::remove_file(&transcript).unwrap();
+    }
+
+    #[test]
+    fn serialize_move_set_is_ordered_and_skips_still_and_bad_moves() {
+        let mut moves = types::MoveSet::new();
+        moves.insert(types::Location { x: 2, y: 1 }, types::NORTH);
+        moves.insert(types::Location { x: 0, y: 1 }, types::STILL);
+        moves.insert(types::Location { x: 1, y: 0 }, types::WEST);
+        moves.insert(types::Location { x: 9, y: 0 }, types::EAST);
+        moves.insert(types::Location { x: 0, y: 0 }, 7);
+        let (mut order, mut out) = (Vec::new(), String::new());
+        serialize_move_set(&moves, 3, 3, false, &mut order, &mut out);
+        assert_eq!(out, "1 0 4 2 1 1 ");
+        out.clear();
+        serialize_move_set(&moves, 3, 3, true, &mut order, &mut out);
+        assert_eq!(out, "1 0 4 0 1 0 2 1 1 ");
+    }
+
+    //cargo test --release -- --ignored --nocapture bench_deserialize_map
+    #[test]
+    #[ignore]
+    fn bench_deserialize_map_50x50() {
+        let (line, _) = frame_line(50, 50);
+        let mut gmp = empty_map(50, 50);
+        let runs = 10000;
+        let start = Instant::now();
+        for _ in 0..runs {
+            deserialize_map(&line, &mut gmp);
+        }
+        let elapsed = start.elapsed();
+        println!("deserialize_map 50x50: {:.1}us per frame", elapsed.as_secs_f64() * 1e6 / runs as f64);
+    }
 }
@@ -1,5 +1,7 @@
 #![allow(warnings)]
 
+use std::collections::BTreeMap;
+
 pub const STILL: u8 = 0;
 pub const NORTH: u8 = 1;
 pub const EAST: u8 = 2;
@@ -15,6 +17,9 @@ pub struct Location {
     pub y: u16,
 }
 
+//One move per site. Ordered, so walking a move set gives the same result every run.
+pub type MoveSet = BTreeMap<Location, u8>;
+
 #[derive(Copy, Clone, Eq, Debug, PartialEq)]
 pub struct Site {
     pub owner: u8,
@@ -74,6 +79,13 @@ impl GameMap {
         }
         loc
     }
+    //The location dx columns and dy rows from l, wrapping around the edges.
+    pub fn get_offset(&self, l: Location, dx: i32, dy: i32) -> Location {
+        Location {
+            x: (l.x as i32 + dx).rem_euclid(self.width as i32) as u16,
+            y: (l.y as i32 + dy).rem_euclid(self.height as i32) as u16,
+        }
+    }
     pub fn get_site(&mut self, l: Location, d: u8) -> &mut Site {
         let loc = self.get_location(l, d);
         &mut self.contents[loc.y as usize][loc.x as usize]
@@ -0,0 +1,212 @@
+// Tallies what players in .hlt replays did with each kind of neighbourhood, to
+// seed new formations in patterns.rs and check troop_strategy against them.
+//
+//   MyBot --mine [--size 3|5] [--player <name>] [--min-count <n>] [--coarse] <replay.hlt>...
+//
+// Every unit of the chosen players with any strength is a sample: the window
+// around it, each site written as whose it is (F ours, E an enemy's, N neutral),
+// its strength bucket and its production bucket, and the direction it moved.
+// Windows are turned and mirrored to one canonical orientation, with the move
+// turned along, so the eight versions of a formation count together. --coarse
+// leaves out strength and production. Without --player the winner of each
+// replay is mined.
+//
+// Each row of the table gives how often the window came up, the troop
+// classify_loc makes of it, how the moves split between STILL, NORTH, EAST,
+// SOUTH and WEST, and how often troop_strategy picked the same move.
+
+use super::*;
+use replay::Replay;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+struct Options {
+    size: i32,
+    player: Option<String>,
+    min_count: u32,
+    coarse: bool,
+    paths: Vec<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { size: 3, player: None, min_count: 1, coarse: false, paths: Vec::new() };
+    let mut args = args.iter();
+    while let Some(a) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} takes a value", name));
+        match a.as_str() {
+            "--size" => options.size = match value(a)?.as_str() { "3" => 3, "5" => 5, s => return Err(format!("--size is 3 or 5, not {}", s)) },
+            "--player" => options.player = Some(value(a)?),
+            "--min-count" => options.min_count = value(a)?.parse().map_err(|_| "--min-count takes a number".to_string())?,
+            "--coarse" => options.coarse = true,
+            _ => options.paths.push(a.clone()),
+        }
+    }
+    if options.paths.is_empty() {
+        return Err("usage: MyBot --mine [--size 3|5] [--player <name>] [--min-count <n>] [--coarse] <replay.hlt>...".to_string())
+    }
+    Ok(options)
+}
+
+fn strength_bucket(s: u8) -> u8 {
+    match s {
+        0 => 0,
+        1..=63 => 1,
+        64..=127 => 2,
+        128..=191 => 3,
+        _ => 4,
+    }
+}
+
+fn production_bucket(p: u8) -> u8 {
+    match p {
+        0..=2 => 0,
+        3..=5 => 1,
+        6..=9 => 2,
+        _ => 3,
+    }
+}
+
+// The troop's name after the board is turned.
+fn turned_name(name: &'static str, quarter_turns: u32) -> &'static str {
+    match (name, quarter_turns % 2) {
+        ("VerticalWall", 1) => "HorizontalWall",
+        ("HorizontalWall", 1) => "VerticalWall",
+        (n, _) => n,
+    }
+}
+
+struct Sample {
+    window: String,
+    troop: &'static str,
+    moved: u8,
+    ours: u8, // What troop_strategy would have done
+}
+
+// The window around loc in canonical orientation, with the moves turned to match.
+fn sample(map: &GameMap, loc: Location, player: u8, moved: u8, ours: u8, size: i32, coarse: bool) -> Sample {
+    let cells = window::cells(map, loc, size, |site| {
+        let owner = if site.owner == player { 'F' } else if site.owner == 0 { 'N' } else { 'E' };
+        if coarse {
+            owner.to_string()
+        } else {
+            format!("{}{}{}", owner, strength_bucket(site.strength), production_bucket(site.production))
+        }
+    });
+    let (o, turned) = window::canonical(&cells);
+    Sample {
+        window: turned.chunks(size as usize).map(|row| row.join(" ")).collect::<Vec<_>>().join(" / "),
+        troop: turned_name(classify_loc(loc, map, player).name(), o.quarter_turns),
+        moved: o.turn(moved),
+        ours: o.turn(ours),
+    }
+}
+
+#[derive(Default)]
+struct Row {
+    troop: &'static str,
+    count: u32,
+    moves: [u32; 5], // By direction
+    agreed: u32,
+}
+
+fn mine_replay(replay: &Replay, options: &Options, rows: &mut BTreeMap<String, Row>) -> u32 {
+    let players = match options.player {
+        Some(ref name) => (1..replay.player_names.len() as u8 + 1)
+            .filter(|p| replay.player_names[*p as usize - 1].contains(name.as_str()))
+            .collect::<Vec<_>>(),
+        None => vec![replay.winner()],
+    };
+    let budget = Budget::start(Duration::from_secs(3600));
+    let weights = Weights::load();
+    let mut samples = 0;
+    for &player in &players {
+        let statics = MapStatics::new(&replay.frames[0], player);
+        for (turn, (map, moved)) in replay.frames.iter().zip(&replay.moves).enumerate() {
+            // The player never follows our plans, so each frame starts without any.
+            let ours = troop_strategy(map, player, &mut trace::TurnTrace::new(turn as u32), &budget, &statics, &weights, &mut Schedule::default());
+            for l in get_units_of_player(player, map) {
+                if map.get_site_ref(l, STILL).strength == 0 {
+                    continue
+                }
+                let d = moved[l.y as usize][l.x as usize];
+                let s = sample(map, l, player, d, *ours.get(&l).unwrap_or(&STILL), options.size, options.coarse);
+                let row = rows.entry(s.window).or_default();
+                row.troop = s.troop;
+                row.count += 1;
+                row.moves[s.moved as usize] += 1;
+                if s.moved == s.ours {
+                    row.agreed += 1;
+                }
+                samples += 1;
+            }
+        }
+    }
+    samples
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let mut rows = BTreeMap::new();
+    let mut samples = 0;
+    for path in &options.paths {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        let replay = Replay::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+        samples += mine_replay(&replay, &options, &mut rows);
+    }
+
+    let percent = |n: u32, of: u32| 100.0 * n as f32 / of.max(1) as f32;
+    println!("# {} samples from {} replays, {}x{} windows", samples, options.paths.len(), options.size, options.size);
+    let mut troops: BTreeMap<&str, (u32, u32)> = BTreeMap::new();
+    for row in rows.values() {
+        let t = troops.entry(row.troop).or_insert((0, 0));
+        t.0 += row.count;
+        t.1 += row.agreed;
+    }
+    println!("# {:16} {:>8} {:>6}", "troop", "samples", "agree");
+    for (troop, &(count, agreed)) in &troops {
+        println!("# {:16} {:>8} {:>5.1}%", troop, count, percent(agreed, count));
+    }
+
+    let mut rows = rows.into_iter().filter(|r| r.1.count >= options.min_count).collect::<Vec<_>>();
+    rows.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(&b.0)));
+    println!("{:>8} {:16} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6}  window", "count", "troop", "still", "north", "east", "south", "west", "agree");
+    for (window, row) in rows {
+        let m = row.moves.iter().map(|n| format!("{:>5.1}%", percent(*n, row.count))).collect::<Vec<_>>();
+        println!("{:>8} {:16} {} {:>5.1}%  {}", row.count, row.troop, m.join(" "), percent(row.agreed, row.count), window);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The same formation turned a quarter gives the same window, with the move
+    // turned back along with it.
+    #[test]
+    fn samples_are_canonical() {
+        let map = scenario::parse("
+            .    .    .    .    .
+            .    .    2:60 .    .
+            .    1:50 1:50 .    .
+            .    .    .    .    .
+            .    .    .    .    .
+        ").unwrap();
+        let turned = scenario::parse("
+            .    .    .    .    .
+            .    .    1:50 .    .
+            .    .    1:50 2:60 .
+            .    .    .    .    .
+            .    .    .    .    .
+        ").unwrap();
+        let loc = Location { x: 2, y: 2 };
+        let a = sample(&map, loc, 1, NORTH, WEST, 3, false);
+        let b = sample(&turned, loc, 1, EAST, NORTH, 3, false);
+        assert_eq!(a.window, b.window);
+        assert_eq!((a.moved, a.ours, a.troop), (b.moved, b.ours, b.troop));
+        assert_eq!(a.troop, "Lance");
+        assert!(a.window.contains("E10"), "{}", a.window);
+        assert_eq!(sample(&map, loc, 1, NORTH, WEST, 5, true).window.split(" / ").count(), 5);
+    }
+}
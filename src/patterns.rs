@@ -0,0 +1,347 @@
+// Troop formations written as small grids around the unit being classified.
+// Each pattern is written once and tried in every rotation and reflection, in
+// table order, so the first formation listed that fits wins.
+//
+// A grid is 3x3 or 5x5 with the unit being classified in the centre. Cells are
+// whitespace separated tokens:
+//   F  ours    E  an enemy's    N  neutral    x  not ours    .  anything
+// then an optional slot, 1 to 3 for a unit of ours taking part in the troop or
+// * for its target, then an optional strength test, <n or >n. So F2>50 is a
+// unit of ours stronger than 50 that goes second in the troop.
+
+use hlt::types::*;
+use std::cell::RefCell;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Role {
+    Interior,
+    VerticalWall,
+    HorizontalWall,
+    Pincer,
+    Pincer3,
+    Lance,
+    Island,
+    Reinforcement,
+    Corner,
+}
+
+impl Role {
+    // Units and whether there is a target. A role without members is just the
+    // unit in the centre.
+    fn slots(self) -> (usize, bool) {
+        match self {
+            Role::Pincer => (2, true),
+            Role::Pincer3 => (3, true),
+            Role::Corner => (3, false),
+            _ => (0, false),
+        }
+    }
+
+    // The role after a quarter turn of the board.
+    fn turned(self) -> Role {
+        match self {
+            Role::VerticalWall => Role::HorizontalWall,
+            Role::HorizontalWall => Role::VerticalWall,
+            r => r,
+        }
+    }
+}
+
+pub struct Pattern {
+    pub role: Role,
+    pub grid: &'static [&'static str],
+}
+
+pub const PATTERNS: &[Pattern] = &[
+    Pattern { role: Role::Interior, grid: &[
+        "F F F",
+        "F F F",
+        "F F F",
+    ]},
+    Pattern { role: Role::Reinforcement, grid: &[
+        ". F .",
+        "F F F",
+        ". F .",
+    ]},
+    Pattern { role: Role::Island, grid: &[
+        ". x .",
+        "x F x",
+        ". x .",
+    ]},
+    Pattern { role: Role::Lance, grid: &[
+        ". F .",
+        "x F x",
+        ". x .",
+    ]},
+    Pattern { role: Role::VerticalWall, grid: &[
+        ". F .",
+        "x F .",
+        ". F .",
+    ]},
+    Pattern { role: Role::Pincer3, grid: &[
+        "F1 x* F3",
+        ".  F2 . ",
+        ".  .  . ",
+    ]},
+    Pattern { role: Role::Pincer, grid: &[
+        "F1 x* .",
+        ".  F2 .",
+        ".  .  .",
+    ]},
+    Pattern { role: Role::Corner, grid: &[
+        ". x  . ",
+        "x F2 F3",
+        ". F1 . ",
+    ]},
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Kind {
+    Friendly,
+    Enemy,
+    Neutral,
+    NotFriendly,
+    Any,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Slot {
+    Nothing,
+    Member(usize),
+    Target,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Cell {
+    dx: i32,
+    dy: i32,
+    kind: Kind,
+    slot: Slot,
+    above: Option<u8>, // Strength has to be more than this
+    below: Option<u8>, // Strength has to be less than this
+}
+
+impl Cell {
+    fn parse(token: &str, dx: i32, dy: i32) -> Result<Cell, String> {
+        let mut chars = token.chars().peekable();
+        let kind = match chars.next() {
+            Some('F') => Kind::Friendly,
+            Some('E') => Kind::Enemy,
+            Some('N') => Kind::Neutral,
+            Some('x') => Kind::NotFriendly,
+            Some('.') => Kind::Any,
+            _ => return Err(format!("bad cell '{}', expected one of F E N x .", token)),
+        };
+        let slot = match chars.peek().cloned() {
+            Some(c @ '1'..='3') => { chars.next(); Slot::Member(c as usize - '1' as usize) },
+            Some('*') => { chars.next(); Slot::Target },
+            _ => Slot::Nothing,
+        };
+        let test = chars.next();
+        let n = chars.collect::<String>();
+        let (above, below) = match test {
+            None => (None, None),
+            Some('>') => (Some(n.parse().map_err(|_| format!("bad strength in '{}'", token))?), None),
+            Some('<') => (None, Some(n.parse().map_err(|_| format!("bad strength in '{}'", token))?)),
+            Some(_) => return Err(format!("bad cell '{}', expected a slot or strength test after {:?}", token, kind)),
+        };
+        if let Slot::Member(_) = slot {
+            if kind != Kind::Friendly {
+                return Err(format!("'{}' is a troop member but not ours", token))
+            }
+        }
+        Ok(Cell { dx, dy, kind, slot, above, below })
+    }
+
+    fn fits(&self, site: &Site, my_id: u8) -> bool {
+        let kind = match self.kind {
+            Kind::Friendly => site.owner == my_id,
+            Kind::Enemy => site.owner != my_id && site.owner != 0,
+            Kind::Neutral => site.owner == 0,
+            Kind::NotFriendly => site.owner != my_id,
+            Kind::Any => true,
+        };
+        kind && self.above.map_or(true, |n| site.strength > n) && self.below.map_or(true, |n| site.strength < n)
+    }
+}
+
+// One rotation or reflection of a pattern.
+pub struct Variant {
+    pub role: Role,
+    cells: Vec<Cell>,
+}
+
+// The troop a variant found around a unit.
+pub struct Match {
+    pub role: Role,
+    pub members: Vec<Location>, // In slot order, or just the unit for roles without slots
+    pub target: Option<Location>,
+}
+
+impl Variant {
+    pub fn fits(&self, loc: Location, map: &GameMap, my_id: u8) -> bool {
+        self.cells.iter().all(|c| c.fits(map.get_site_ref(map.get_offset(loc, c.dx, c.dy), STILL), my_id))
+    }
+
+    // Where the variant's slots fall with the unit at loc, whether or not it fits.
+    pub fn locate(&self, loc: Location, map: &GameMap) -> Match {
+        let mut members = Vec::new();
+        let mut target = None;
+        for c in &self.cells {
+            match c.slot {
+                Slot::Member(i) => {
+                    if members.len() <= i {
+                        members.resize(i + 1, loc);
+                    }
+                    members[i] = map.get_offset(loc, c.dx, c.dy);
+                },
+                Slot::Target => target = Some(map.get_offset(loc, c.dx, c.dy)),
+                Slot::Nothing => (),
+            }
+        }
+        if members.is_empty() {
+            members.push(loc);
+        }
+        Match { role: self.role, members, target }
+    }
+}
+
+fn compile_pattern(pattern: &Pattern) -> Result<Vec<Cell>, String> {
+    let size = pattern.grid.len();
+    if size != 3 && size != 5 {
+        return Err(format!("{:?} grid is {} rows, expected 3 or 5", pattern.role, size))
+    }
+    let half = size as i32 / 2;
+    let mut cells = Vec::new();
+    for (y, row) in pattern.grid.iter().enumerate() {
+        let tokens = row.split_whitespace().collect::<Vec<_>>();
+        if tokens.len() != size {
+            return Err(format!("{:?} grid row '{}' isn't {} cells", pattern.role, row, size))
+        }
+        for (x, token) in tokens.into_iter().enumerate() {
+            cells.push(Cell::parse(token, x as i32 - half, y as i32 - half)?);
+        }
+    }
+
+    let centre = cells.iter().find(|c| c.dx == 0 && c.dy == 0).unwrap();
+    if centre.kind != Kind::Friendly || centre.slot == Slot::Target {
+        return Err(format!("{:?} centre has to be one of ours", pattern.role))
+    }
+    let members = cells.iter().filter(|c| matches!(c.slot, Slot::Member(_))).count();
+    let targets = cells.iter().filter(|c| c.slot == Slot::Target).count();
+    let (wanted_members, wanted_target) = pattern.role.slots();
+    let numbered = (0..members).all(|i| cells.iter().any(|c| c.slot == Slot::Member(i)));
+    if members != wanted_members || targets != wanted_target as usize || !numbered {
+        return Err(format!("{:?} needs {} members numbered from 1 and {} target, found {} and {}",
+                           pattern.role, wanted_members, wanted_target as usize, members, targets))
+    }
+
+    // Cells that accept anything and aren't in the troop don't need checking.
+    cells.retain(|c| c.kind != Kind::Any || c.slot != Slot::Nothing || c.above.is_some() || c.below.is_some());
+    Ok(cells)
+}
+
+// Expands every pattern into its rotations and reflections, dropping any that
+// ask for the same sites as one before it.
+pub fn compile(patterns: &[Pattern]) -> Result<Vec<Variant>, String> {
+    let mut variants = Vec::new();
+    for pattern in patterns {
+        let cells = compile_pattern(pattern)?;
+        let mut seen: Vec<Vec<Cell>> = Vec::new();
+        for mirrored in &[false, true] {
+            for quarter_turns in 0..4 {
+                let mut turned = cells.iter()
+                    .map(|c| {
+                        let (mut dx, mut dy) = (if *mirrored { -c.dx } else { c.dx }, c.dy);
+                        for _ in 0..quarter_turns {
+                            let (x, y) = (-dy, dx);
+                            dx = x;
+                            dy = y;
+                        }
+                        Cell { dx, dy, ..*c }
+                    })
+                    .collect::<Vec<_>>();
+                turned.sort_by_key(|c| (c.dy, c.dx));
+                let sites = turned.iter().map(|c| Cell { slot: Slot::Nothing, ..*c }).collect::<Vec<_>>();
+                if seen.contains(&sites) {
+                    continue
+                }
+                seen.push(sites);
+                let role = if quarter_turns % 2 == 1 { pattern.role.turned() } else { pattern.role };
+                variants.push(Variant { role, cells: turned });
+            }
+        }
+    }
+    Ok(variants)
+}
+
+thread_local! {
+    //PATTERNS expanded, done the first time a unit is classified.
+    static VARIANTS: RefCell<Option<Vec<Variant>>> = RefCell::new(None);
+}
+
+// The first variant of PATTERNS that fits around the unit at loc.
+pub fn find(loc: Location, map: &GameMap, my_id: u8) -> Option<Match> {
+    VARIANTS.with(|variants| {
+        let mut variants = variants.borrow_mut();
+        let variants = variants.get_or_insert_with(|| compile(PATTERNS).unwrap_or_else(|e| panic!("bad pattern: {}", e)));
+        variants.iter().find(|v| v.fits(loc, map, my_id)).map(|v| v.locate(loc, map))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+
+    #[test]
+    fn patterns_compile() {
+        let variants = compile(PATTERNS).unwrap();
+        let count = |role| variants.iter().filter(|v| v.role == role).count();
+        assert_eq!((count(Role::Interior), count(Role::Lance), count(Role::Pincer), count(Role::Pincer3), count(Role::Corner)), (1, 4, 8, 4, 4));
+        assert_eq!((count(Role::VerticalWall), count(Role::HorizontalWall)), (2, 2));
+    }
+
+    #[test]
+    fn compile_rejects_bad_patterns() {
+        let bad = |role, grid| compile(&[Pattern { role, grid }]).is_err();
+        assert!(bad(Role::Interior, &["F F", "F F"]));
+        assert!(bad(Role::Interior, &["F F F", "F x F", "F F F"]));
+        assert!(bad(Role::Interior, &["F F F", "F F F", "F F q"]));
+        assert!(bad(Role::Pincer, &["F1 x .", ". F2 .", ". . ."]));
+        assert!(bad(Role::Pincer, &["x1 x* .", ". F2 .", ". . ."]));
+        assert!(bad(Role::Pincer, &["F1 x* .", ". F3 .", ". . ."]));
+        assert!(bad(Role::Lance, &[". F .", "x F x", ". x>z ."]));
+    }
+
+    // A 5x5 formation with strength tests, found whichever way round it is.
+    #[test]
+    fn five_by_five_patterns_match_in_every_orientation() {
+        const LONG_PINCER: &[Pattern] = &[Pattern { role: Role::Pincer, grid: &[
+            ".  .  E*<100 . .",
+            ".  .  N      . .",
+            ".  .  F1>50  . .",
+            ".  .  .      . .",
+            ".  .  F2     . .",
+        ]}];
+        let variants = compile(LONG_PINCER).unwrap();
+        assert_eq!(variants.len(), 4);
+        let map = scenario::parse("
+            .    .    .    .    .    .    .
+            .    .    .    .    .    .    .
+            .    .    .    .    .    .    .
+            2:90 .    1:60 .    1:10 .    .
+            .    .    .    .    .    .    .
+            .    .    .    .    .    .    .
+            .    .    .    .    .    .    .
+        ").unwrap();
+        let loc = Location { x: 2, y: 3 };
+        let found = variants.iter().find(|v| v.fits(loc, &map, 1)).map(|v| v.locate(loc, &map)).unwrap();
+        assert_eq!(found.members, vec![loc, Location { x: 4, y: 3 }]);
+        assert_eq!(found.target, Some(Location { x: 0, y: 3 }));
+
+        let mut weak = map.clone();
+        weak.contents[3][2].strength = 50;
+        assert!(!variants.iter().any(|v| v.fits(loc, &weak, 1)));
+    }
+}
@@ -0,0 +1,269 @@
+// A small neural network that scores the five directions for a unit from the
+// window::features around it, fitted offline on rows from --export.
+//
+// Weights are a text file of whitespace separated tokens, # to the end of a
+// line being a comment. It starts with the window size, then the layers in
+// order, each a keyword followed by its shape and then its numbers:
+//
+//   window 5
+//   conv 3 16        kernel size and output channels, no padding, then
+//                    weights by [output][y][x][input] and a bias per output
+//   relu
+//   dense 32         outputs, then weights by [output][input] and a bias per output
+//   relu
+//   dense 5
+//   softmax
+//
+// Activations are laid out [y][x][channel] like the features, and flattened
+// as they are for dense layers. The last layer has to give five values, one per
+// direction in window orientation, STILL first.
+
+use hlt::types::*;
+use std::env;
+use std::fs;
+use window::{self, CHANNELS};
+
+enum Layer {
+    Conv { size: usize, width: usize, inputs: usize, outputs: usize, weights: Vec<f32>, bias: Vec<f32> },
+    Dense { inputs: usize, outputs: usize, weights: Vec<f32>, bias: Vec<f32> },
+    Relu,
+    Softmax,
+}
+
+pub struct Policy {
+    pub window: i32,
+    layers: Vec<Layer>,
+}
+
+struct Tokens<'a> {
+    tokens: Box<dyn Iterator<Item = &'a str> + 'a>,
+}
+
+impl<'a> Tokens<'a> {
+    fn word(&mut self) -> Option<&'a str> {
+        self.tokens.next()
+    }
+
+    fn number<T: ::std::str::FromStr>(&mut self, what: &str) -> Result<T, String> {
+        match self.tokens.next() {
+            Some(t) => t.parse().map_err(|_| format!("bad {} '{}'", what, t)),
+            None => Err(format!("missing {}", what)),
+        }
+    }
+
+    fn numbers(&mut self, n: usize, what: &str) -> Result<Vec<f32>, String> {
+        (0..n).map(|_| self.number(what)).collect()
+    }
+}
+
+impl Policy {
+    pub fn parse(s: &str) -> Result<Policy, String> {
+        let mut tokens = Tokens {
+            tokens: Box::new(s.lines().flat_map(|l| l.split('#').next().unwrap().split_whitespace())),
+        };
+        if tokens.word() != Some("window") {
+            return Err("weights have to start with the window size".to_string())
+        }
+        let window: usize = tokens.number("window size")?;
+        if window % 2 == 0 {
+            return Err(format!("window size {} isn't odd", window))
+        }
+
+        // The shape coming into each layer, width x width x channels.
+        let (mut width, mut channels) = (window, CHANNELS);
+        let mut layers = Vec::new();
+        while let Some(word) = tokens.word() {
+            let layer = match word {
+                "conv" => {
+                    let size: usize = tokens.number("kernel size")?;
+                    let outputs: usize = tokens.number("conv outputs")?;
+                    if size == 0 || size > width {
+                        return Err(format!("conv kernel {} doesn't fit a {}x{} input", size, width, width))
+                    }
+                    let layer = Layer::Conv {
+                        size,
+                        width,
+                        inputs: channels,
+                        outputs,
+                        weights: tokens.numbers(outputs * size * size * channels, "conv weight")?,
+                        bias: tokens.numbers(outputs, "conv bias")?,
+                    };
+                    width = width - size + 1;
+                    channels = outputs;
+                    layer
+                },
+                "dense" => {
+                    let inputs = width * width * channels;
+                    let outputs: usize = tokens.number("dense outputs")?;
+                    let layer = Layer::Dense {
+                        inputs,
+                        outputs,
+                        weights: tokens.numbers(outputs * inputs, "dense weight")?,
+                        bias: tokens.numbers(outputs, "dense bias")?,
+                    };
+                    width = 1;
+                    channels = outputs;
+                    layer
+                },
+                "relu" => Layer::Relu,
+                "softmax" => Layer::Softmax,
+                w => return Err(format!("unknown layer '{}'", w)),
+            };
+            layers.push(layer);
+        }
+        if width * width * channels != DIRECTIONS.len() {
+            return Err(format!("the last layer gives {} values, expected one per direction", width * width * channels))
+        }
+        Ok(Policy { window: window as i32, layers })
+    }
+
+    // The weights in A2I_POLICY, or policy.txt if there is one. None if there
+    // are no weights; bad weights are reported and ignored so the bot still plays.
+    pub fn load() -> Option<Policy> {
+        let path = env::var("A2I_POLICY").ok();
+        let text = match fs::read_to_string(path.as_ref().map_or("policy.txt", |p| p.as_str())) {
+            Ok(text) => text,
+            Err(e) => {
+                if let Some(p) = path {
+                    eprintln!("can't read policy {}: {}", p, e);
+                }
+                return None
+            },
+        };
+        match Policy::parse(&text) {
+            Ok(policy) => Some(policy),
+            Err(e) => {
+                eprintln!("bad policy: {}", e);
+                None
+            },
+        }
+    }
+
+    // A score for each direction, in window orientation.
+    pub fn forward(&self, features: &[f32]) -> Vec<f32> {
+        let mut x = features.to_vec();
+        for layer in &self.layers {
+            x = match *layer {
+                Layer::Conv { size, width, inputs, outputs, ref weights, ref bias } => {
+                    let out_width = width - size + 1;
+                    let mut y = Vec::with_capacity(out_width * out_width * outputs);
+                    for oy in 0..out_width {
+                        for ox in 0..out_width {
+                            for (o, b) in bias.iter().enumerate() {
+                                let mut sum = *b;
+                                for ky in 0..size {
+                                    for kx in 0..size {
+                                        let at = ((oy + ky) * width + ox + kx) * inputs;
+                                        let w = ((o * size + ky) * size + kx) * inputs;
+                                        sum += x[at..at + inputs].iter().zip(&weights[w..w + inputs]).map(|(a, b)| a * b).sum::<f32>();
+                                    }
+                                }
+                                y.push(sum);
+                            }
+                        }
+                    }
+                    y
+                },
+                Layer::Dense { inputs, outputs, ref weights, ref bias } => {
+                    (0..outputs)
+                        .map(|o| bias[o] + x.iter().zip(&weights[o * inputs..(o + 1) * inputs]).map(|(a, b)| a * b).sum::<f32>())
+                        .collect()
+                },
+                Layer::Relu => x.into_iter().map(|v| v.max(0.0)).collect(),
+                Layer::Softmax => {
+                    let max = x.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                    let exp = x.iter().map(|v| (v - max).exp()).collect::<Vec<_>>();
+                    let total: f32 = exp.iter().sum();
+                    exp.into_iter().map(|v| v / total).collect()
+                },
+            };
+        }
+        x
+    }
+
+    // The best scoring move for the unit at l, on the real board.
+    pub fn best_move(&self, map: &GameMap, l: Location, my_id: u8) -> u8 {
+        let (o, features) = window::features(map, l, my_id, self.window);
+        let scores = self.forward(&features);
+        let best = DIRECTIONS.iter().cloned()
+            .max_by(|a, b| scores[*a as usize].partial_cmp(&scores[*b as usize]).unwrap_or(::std::cmp::Ordering::Equal))
+            .unwrap();
+        o.unturn(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+    use std::time::Instant;
+    use testmaps::Rng;
+
+    // Scores each direction by the strength of the site it leads to, so units
+    // head for the strongest neighbour whichever way the window is turned.
+    fn towards_strength() -> String {
+        let neighbour = [None, Some(1), Some(5), Some(7), Some(3)]; // Cell of each direction in a 3x3 window
+        let rows = neighbour.iter()
+            .map(|cell| (0..9 * CHANNELS)
+                .map(|i| if Some(i) == cell.map(|c| c * CHANNELS + 2) { "1" } else { "0" })
+                .collect::<Vec<_>>()
+                .join(" "))
+            .collect::<Vec<_>>();
+        format!("window 3 # 3x3x4 inputs\ndense 5\n{}\n0.01 0 0 0 0\nsoftmax\n", rows.join("\n"))
+    }
+
+    #[test]
+    fn parse_checks_shapes() {
+        let weights = towards_strength();
+        assert!(Policy::parse(&weights).is_ok());
+        assert!(Policy::parse("window 4 dense 5").is_err());
+        assert!(Policy::parse("dense 5").is_err());
+        assert!(Policy::parse(&weights.replace("0.01", "")).is_err());
+        assert!(Policy::parse(&weights.replace("softmax", "sigmoid")).is_err());
+        assert!(Policy::parse(&weights.replace("softmax", "dense 2 1 1 1 1 1 1 1 1 1 1 0 0")).is_err());
+        assert!(Policy::parse("window 3 conv 5 1").is_err());
+    }
+
+    #[test]
+    fn forward_scores_directions() {
+        let policy = Policy::parse(&towards_strength()).unwrap();
+        let map = scenario::parse("
+            .    .    .    .
+            .    .    .    .
+            .    1:50 0:9  .
+            .    .    .    .
+        ").unwrap();
+        let loc = Location { x: 1, y: 2 };
+        let (_, features) = window::features(&map, loc, 1, 3);
+        let scores = policy.forward(&features);
+        assert!((scores.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert_eq!(policy.best_move(&map, loc, 1), EAST);
+        assert_eq!(policy.best_move(&map, Location { x: 0, y: 0 }, 1), STILL);
+    }
+
+    fn random_layer(rng: &mut Rng, n: usize) -> String {
+        (0..n).map(|_| format!("{:.3}", rng.below(2000) as f32 / 1000.0 - 1.0)).collect::<Vec<_>>().join(" ")
+    }
+
+    // cargo test --release policy_bench -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn policy_bench() {
+        let mut rng = Rng::new(7);
+        let weights = format!("window 7 conv 3 16 {} {} relu conv 3 16 {} {} relu dense 64 {} {} relu dense 5 {} {} softmax",
+                              random_layer(&mut rng, 16 * 9 * 4), random_layer(&mut rng, 16),
+                              random_layer(&mut rng, 16 * 9 * 16), random_layer(&mut rng, 16),
+                              random_layer(&mut rng, 64 * 9 * 16), random_layer(&mut rng, 64),
+                              random_layer(&mut rng, 5 * 64), random_layer(&mut rng, 5));
+        let policy = Policy::parse(&weights).unwrap();
+        let mut map = GameMap { width: 50, height: 50, contents: Vec::new() };
+        map.contents = (0..50).map(|_| (0..50).map(|_| Site { owner: 1, strength: rng.below(256) as u8, production: 3 }).collect()).collect();
+        let start = Instant::now();
+        for y in 0..50 {
+            for x in 0..50 {
+                policy.best_move(&map, Location { x, y }, 1);
+            }
+        }
+        println!("{:?} for 2500 units", start.elapsed());
+    }
+}
@@ -0,0 +1,177 @@
+// Hand-written tactical positions with moves we expect or forbid, scored for
+// each strategy on the moves it would send after validate::repair, so
+// behaviour regressions show up without playing full games.
+//
+// cargo test puzzles -- --nocapture prints the score table.
+
+use super::*;
+use scenario;
+use sim;
+use std::time::Duration;
+
+enum Check {
+    Expect(u16, u16, u8), // The unit at x, y makes this move
+    Forbid(u16, u16, u8), // The unit at x, y doesn't make this move
+    Captured(u16, u16), // We own x, y after the turn
+    NoWaste, // Nothing of ours is lost to the 255 cap
+}
+
+struct Puzzle {
+    name: &'static str,
+    map: &'static str,
+    checks: &'static [Check],
+}
+
+use self::Check::*;
+
+const PUZZLES: &[Puzzle] = &[
+    Puzzle {
+        name: "no losing battle against neutral",
+        map: "
+            .    .    .    .    .
+            .    0:90 0:90 0:90 .
+            .    0:90 1:50 0:80 .
+            .    0:90 0:90 0:90 .
+            .    .    .    .    .
+        ",
+        checks: &[Forbid(2, 2, NORTH), Forbid(2, 2, EAST), Forbid(2, 2, SOUTH), Forbid(2, 2, WEST)],
+    },
+    Puzzle {
+        name: "no losing battle against an enemy",
+        map: "
+            0:200 0:200 0:200 0:200 0:200
+            0:200 1:0   1:0   1:0   0:200
+            0:200 1:0   1:60  2:150 0:200
+            0:200 1:0   1:0   1:0   0:200
+            0:200 0:200 0:200 0:200 0:200
+        ",
+        checks: &[Forbid(2, 2, EAST)],
+    },
+    Puzzle {
+        name: "take the weak neighbour",
+        map: "
+            0:200 0:200 0:200 0:200 0:200
+            0:200 0:200 0:30  0:200 0:200
+            0:200 0:200 1:100 0:200 0:200
+            0:200 0:200 0:200 0:200 0:200
+            0:200 0:200 0:200 0:200 0:200
+        ",
+        checks: &[Expect(2, 2, NORTH), Captured(2, 1)],
+    },
+    Puzzle {
+        name: "prefer the cheaper capture",
+        map: "
+            0:250 0:250 0:250 0:250 0:250
+            0:250 0:250 0:60  0:250 0:250
+            0:250 0:250 1:200 0:10  0:250
+            0:250 0:250 0:250 0:250 0:250
+            0:250 0:250 0:250 0:250 0:250
+        ",
+        checks: &[Expect(2, 2, EAST)],
+    },
+    Puzzle {
+        name: "combine two units to capture",
+        map: "
+            0:200 0:200 0:200 0:200 0:200
+            0:200 0:200 1:60  0:200 0:200
+            0:200 1:60  0:100 0:200 0:200
+            0:200 0:200 0:200 0:200 0:200
+            0:200 0:200 0:200 0:200 0:200
+        ",
+        checks: &[Captured(2, 2)],
+    },
+    Puzzle {
+        name: "combine three units to capture",
+        map: "
+            0:200 0:200 0:200 0:200 0:200
+            0:200 1:40  0:100 1:40  0:200
+            0:200 0:200 1:40  0:200 0:200
+            0:200 0:200 0:200 0:200 0:200
+            0:200 0:200 0:200 0:200 0:200
+        ",
+        checks: &[Captured(2, 1)],
+    },
+    Puzzle {
+        name: "don't merge two 200s",
+        map: "
+            0:250 0:250 0:250 0:250 0:250 0:250
+            0:250 1:200 1:200 1:200 1:200 0:250
+            0:250 1:200 1:200 1:200 1:200 0:250
+            0:250 0:250 0:250 0:250 0:250 0:250
+        ",
+        checks: &[NoWaste],
+    },
+    Puzzle {
+        name: "interior units head for the border",
+        map: "
+            1:0   1:0   1:0   1:0   1:0   0:5
+            1:0   1:0   1:0   1:0   1:0   0:5
+            1:0   1:0   1:100 1:0   1:0   0:5
+            1:0   1:0   1:0   1:0   1:0   0:5
+            1:0   1:0   1:0   1:0   1:0   0:5
+        ",
+        checks: &[Forbid(2, 2, STILL), Forbid(2, 2, WEST), NoWaste],
+    },
+];
+
+type Runner = fn(&GameMap, &MapStatics) -> MoveSet;
+
+fn simple(map: &GameMap, _statics: &MapStatics) -> MoveSet {
+    get_units_of_player(1, map).into_iter()
+        .map(|l| (l, get_best_move_simple(l, map, 1, &Weights::default(), &mut trace::TurnTrace::new(0))))
+        .collect()
+}
+
+fn max_capture(map: &GameMap, _statics: &MapStatics) -> MoveSet {
+    max_capture_strategy(map, 1, &mut trace::TurnTrace::new(0), &Budget::start(Duration::from_secs(60)))
+}
+
+fn troop(map: &GameMap, statics: &MapStatics) -> MoveSet {
+    troop_strategy(map, 1, &mut trace::TurnTrace::new(0), &Budget::start(Duration::from_secs(60)), statics, &Weights::default(), &mut Schedule::default())
+}
+
+// The puzzles each strategy doesn't solve yet. Any other puzzle it fails is
+// a regression and fails the suite; take a puzzle off when it gets solved.
+const STRATEGIES: &[(&str, Runner, &[&str])] = &[
+    ("get_best_move_simple", simple, &["combine two units to capture", "combine three units to capture", "interior units head for the border"]),
+    ("max_capture_strategy", max_capture, &["combine two units to capture", "combine three units to capture", "interior units head for the border"]),
+    ("troop_strategy", troop, &[]),
+];
+
+// How many of the puzzle's checks the moves pass, once repaired.
+fn score(puzzle: &Puzzle, map: &GameMap, mut moves: MoveSet) -> usize {
+    validate::repair(map, 1, &mut moves, &Budget::start(Duration::from_secs(60)));
+    let moves = &moves;
+    let outcome = sim::simulate(map, 1, moves);
+    let owner = |x, y| outcome.map.contents[y as usize][x as usize].owner;
+    let move_at = |x, y| *moves.get(&Location { x, y }).unwrap_or(&STILL);
+    puzzle.checks.iter()
+        .filter(|check| match **check {
+            Expect(x, y, d) => move_at(x, y) == d,
+            Forbid(x, y, d) => move_at(x, y) != d,
+            Captured(x, y) => owner(x, y) == 1,
+            NoWaste => outcome.wasted == 0,
+        })
+        .count()
+}
+
+#[test]
+fn puzzles() {
+    let total: usize = PUZZLES.iter().map(|p| p.checks.len()).sum();
+    let mut failures = Vec::new();
+    for &(name, run, unsolved) in STRATEGIES {
+        let mut passed = 0;
+        for puzzle in PUZZLES {
+            let map = scenario::parse(puzzle.map).unwrap();
+            let statics = MapStatics::new(&map, 1);
+            let s = score(puzzle, &map, run(&map, &statics));
+            println!("{:22} {:38} {}/{}", name, puzzle.name, s, puzzle.checks.len());
+            passed += s;
+            if s < puzzle.checks.len() && !unsolved.contains(&puzzle.name) {
+                failures.push(format!("{} fails '{}'", name, puzzle.name));
+            }
+        }
+        println!("{:22} {:38} {}/{}", name, "total", passed, total);
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,297 @@
+// Reads the .hlt replays the environment writes after every game: a JSON object
+// with the map size, player names, productions, every frame as [owner, strength]
+// pairs by [y][x], and every turn's moves as directions by [y][x].
+
+use hlt::types::*;
+
+enum Json {
+    Literal, // true, false or null, which replays don't use
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, what: &str) -> Result<T, String> {
+        Err(format!("{} at byte {}", what, self.at))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.at < self.s.len() && (self.s[self.at] as char).is_whitespace() {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.s.get(self.at).cloned()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.peek() != Some(c) {
+            return self.error(&format!("expected '{}'", c as char))
+        }
+        self.at += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str) -> Result<Json, String> {
+        if !self.s[self.at..].starts_with(word.as_bytes()) {
+            return self.error("bad literal")
+        }
+        self.at += word.len();
+        Ok(Json::Literal)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::Str),
+            Some(b't') => self.literal("true"),
+            Some(b'f') => self.literal("false"),
+            Some(b'n') => self.literal("null"),
+            Some(_) => self.number(),
+            None => self.error("unexpected end"),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.at;
+        while self.at < self.s.len() && b"+-.eE0123456789".contains(&self.s[self.at]) {
+            self.at += 1;
+        }
+        match ::std::str::from_utf8(&self.s[start..self.at]).ok().and_then(|n| n.parse().ok()) {
+            Some(n) => Ok(Json::Number(n)),
+            None => self.error("bad number"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let c = match self.s.get(self.at) {
+                Some(c) => *c,
+                None => return self.error("unterminated string"),
+            };
+            self.at += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let e = self.s.get(self.at).cloned();
+                    self.at += 1;
+                    match e {
+                        Some(b'n') => bytes.push(b'\n'),
+                        Some(b't') => bytes.push(b'\t'),
+                        Some(b'r') => bytes.push(b'\r'),
+                        Some(b'b') => bytes.push(8),
+                        Some(b'f') => bytes.push(12),
+                        Some(b'u') => {
+                            let hex = self.s.get(self.at..self.at + 4).and_then(|h| ::std::str::from_utf8(h).ok());
+                            let c = hex.and_then(|h| u32::from_str_radix(h, 16).ok()).and_then(::std::char::from_u32);
+                            match c {
+                                Some(c) => bytes.extend(c.to_string().bytes()),
+                                None => return self.error("bad \\u escape"),
+                            }
+                            self.at += 4;
+                        },
+                        Some(c) => bytes.push(c),
+                        None => return self.error("unterminated string"),
+                    }
+                },
+                c => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).or_else(|_| self.error("string isn't utf-8"))
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.at += 1;
+            return Ok(Json::Array(items))
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.at += 1,
+                Some(b']') => { self.at += 1; return Ok(Json::Array(items)) },
+                _ => return self.error("expected ',' or ']'"),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.at += 1;
+            return Ok(Json::Object(fields))
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.at += 1,
+                Some(b'}') => { self.at += 1; return Ok(Json::Object(fields)) },
+                _ => return self.error("expected ',' or '}'"),
+            }
+        }
+    }
+}
+
+impl Json {
+    fn field(&self, name: &str) -> Result<&Json, String> {
+        match *self {
+            Json::Object(ref fields) => fields.iter().find(|f| f.0 == name).map(|f| &f.1).ok_or(format!("no '{}' field", name)),
+            _ => Err(format!("expected an object holding '{}'", name)),
+        }
+    }
+
+    fn items(&self) -> Result<&[Json], String> {
+        match *self {
+            Json::Array(ref items) => Ok(items),
+            _ => Err("expected an array".to_string()),
+        }
+    }
+
+    fn number(&self) -> Result<f64, String> {
+        match *self {
+            Json::Number(n) => Ok(n),
+            _ => Err("expected a number".to_string()),
+        }
+    }
+
+    fn byte(&self) -> Result<u8, String> {
+        let n = self.number()?;
+        if !(0.0..=255.0).contains(&n) {
+            return Err(format!("{} doesn't fit a site", n))
+        }
+        Ok(n as u8)
+    }
+}
+
+// A [y][x] grid of the replay, checked against the map size.
+fn grid<T, F>(j: &Json, width: u16, height: u16, cell: F) -> Result<Vec<Vec<T>>, String>
+    where F: Fn(&Json) -> Result<T, String> {
+    let rows = j.items()?;
+    if rows.len() != height as usize {
+        return Err(format!("grid has {} rows, expected {}", rows.len(), height))
+    }
+    rows.iter()
+        .map(|row| {
+            let row = row.items()?;
+            if row.len() != width as usize {
+                return Err(format!("grid row has {} sites, expected {}", row.len(), width))
+            }
+            row.iter().map(&cell).collect()
+        })
+        .collect()
+}
+
+pub struct Replay {
+    pub player_names: Vec<String>, // Player id n is player_names[n - 1]
+    pub frames: Vec<GameMap>, // The map at the start of each turn, frames[0] being the initial map
+    pub moves: Vec<Vec<Vec<u8>>>, // What was sent on each turn, by [turn][y][x], one fewer than frames
+}
+
+impl Replay {
+    pub fn parse(s: &str) -> Result<Replay, String> {
+        let json = Parser { s: s.as_bytes(), at: 0 }.value()?;
+        let width = json.field("width")?.number()? as u16;
+        let height = json.field("height")?.number()? as u16;
+        let player_names = json.field("player_names")?.items()?.iter()
+            .map(|n| match *n { Json::Str(ref s) => Ok(s.clone()), _ => Err("player names have to be strings".to_string()) })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let productions = grid(json.field("productions")?, width, height, |p| p.byte())?;
+        let mut frames = Vec::new();
+        for frame in json.field("frames")?.items()? {
+            let mut contents = grid(frame, width, height, |pair| {
+                let pair = pair.items()?;
+                if pair.len() != 2 {
+                    return Err("frame sites have to be [owner, strength]".to_string())
+                }
+                Ok(Site { owner: pair[0].byte()?, strength: pair[1].byte()?, production: 0 })
+            })?;
+            for (row, production_row) in contents.iter_mut().zip(&productions) {
+                for (site, p) in row.iter_mut().zip(production_row) {
+                    site.production = *p;
+                }
+            }
+            frames.push(GameMap { width, height, contents });
+        }
+
+        let mut moves = Vec::new();
+        for turn in json.field("moves")?.items()? {
+            moves.push(grid(turn, width, height, |d| match d.byte()? {
+                d if d <= WEST => Ok(d),
+                d => Err(format!("bad direction {}", d)),
+            })?);
+        }
+        if frames.is_empty() || moves.len() + 1 != frames.len() {
+            return Err(format!("{} frames and {} turns of moves, expected one more frame than turns", frames.len(), moves.len()))
+        }
+        Ok(Replay { player_names, frames, moves })
+    }
+
+    // Each player's place at the end, 1 for the winner, by [player id - 1].
+    // Holding more sites on the last frame is better, then lasting longer.
+    pub fn ranks(&self) -> Vec<u8> {
+        let players = self.player_names.len() as u8;
+        let sites = |frame: &GameMap, p: u8| frame.contents.iter().flat_map(|row| row.iter()).filter(|s| s.owner == p).count();
+        let standing = |p: u8| {
+            let lasted = self.frames.iter().rposition(|f| sites(f, p) > 0).unwrap_or(0);
+            (sites(self.frames.last().unwrap(), p), lasted)
+        };
+        let mut order = (1..players + 1).collect::<Vec<_>>();
+        order.sort_by_key(|p| ::std::cmp::Reverse(standing(*p)));
+        (1..players + 1).map(|p| order.iter().position(|o| *o == p).unwrap() as u8 + 1).collect()
+    }
+
+    pub fn winner(&self) -> u8 {
+        self.ranks().iter().position(|r| *r == 1).map_or(1, |i| i as u8 + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TINY: &str = r#"{"version": 11, "width": 2, "height": 1, "num_players": 2, "num_frames": 2, "ended": true, "seed": null,
+        "player_names": ["Asp2Insp1", "some \"quoted\" bot!"],
+        "productions": [[3, 1]],
+        "frames": [[[[1, 10], [2, 5]]], [[[1, 5], [0, 0]]]],
+        "moves": [[[2, 0]]]}"#;
+
+    #[test]
+    fn parse_reads_a_replay() {
+        let replay = Replay::parse(TINY).unwrap();
+        assert_eq!((replay.frames[0].width, replay.frames[0].height), (2, 1));
+        assert_eq!(replay.player_names, vec!["Asp2Insp1".to_string(), "some \"quoted\" bot!".to_string()]);
+        assert_eq!(replay.frames.len(), 2);
+        assert_eq!(replay.frames[0].contents[0][1], Site { owner: 2, strength: 5, production: 1 });
+        assert_eq!(replay.frames[1].contents[0][0], Site { owner: 1, strength: 5, production: 3 });
+        assert_eq!(replay.moves, vec![vec![vec![EAST, STILL]]]);
+        assert_eq!(replay.ranks(), vec![1, 2]);
+        assert_eq!(replay.winner(), 1);
+    }
+
+    #[test]
+    fn parse_rejects_broken_replays() {
+        assert!(Replay::parse(&TINY.replace("[[2, 0]]", "[[2, 0, 1]]")).is_err());
+        assert!(Replay::parse(&TINY.replace("[[2, 0]]", "[[7, 0]]")).is_err());
+        assert!(Replay::parse(&TINY.replace("\"moves\": [[[2, 0]]]", "\"moves\": []")).is_err());
+        assert!(Replay::parse(&TINY[..TINY.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+// Sends interior strength to the frontier as one allocation instead of unit by
+// unit, so reinforcements spread over the targets that need them rather than
+// piling onto the same sites and losing strength to the 255 cap.
+//
+// It's an approximate min-cost flow: every interior unit supplies its strength,
+// every frontier target demands enough strength to capture it, and an arc costs
+// the target's payback plus the walk there. Arcs are taken cheapest first while
+// the target still needs strength, which is the greedy answer to the transport
+// problem. Units left over once demand is met go to their cheapest target anyway.
+// Each unit then takes a step towards its target, but only onto a site that
+// stays within the cap with what's already headed there.
+
+use capture::CAP;
+use frontier::{Frontier, Target};
+use hlt::types::*;
+use statics::MapStatics;
+use std::collections::BTreeMap;
+
+// Cheapest targets each unit is offered, enough to spread out without making
+// the arcs grow with units times targets.
+const ARCS_PER_UNIT: usize = 8;
+
+// A step for each unit in units, STILL when no step towards its target fits
+// under the cap.
+pub fn route(map: &GameMap, my_id: u8, frontier: &Frontier, statics: &MapStatics, units: &[Location]) -> BTreeMap<Location, u8> {
+    let strength = |l: Location| map.get_site_ref(l, STILL).strength as u16;
+    let cost = |u: Location, t: &Target| t.payback + statics.distance(u, t.loc) as f32;
+    let mut routes = BTreeMap::new();
+    if frontier.targets.is_empty() {
+        for u in units {
+            routes.insert(*u, STILL);
+        }
+        return routes
+    }
+
+    // (cost, unit, target) for each unit's cheapest targets.
+    let mut arcs = Vec::new();
+    for (i, u) in units.iter().enumerate() {
+        let mut offered = (0..frontier.targets.len()).collect::<Vec<_>>();
+        let by_cost = |a: &usize, b: &usize| cost(*u, &frontier.targets[*a]).partial_cmp(&cost(*u, &frontier.targets[*b])).unwrap().then(a.cmp(b));
+        if offered.len() > ARCS_PER_UNIT {
+            offered.select_nth_unstable_by(ARCS_PER_UNIT - 1, by_cost);
+            offered.truncate(ARCS_PER_UNIT);
+        }
+        offered.sort_by(by_cost);
+        for t in offered {
+            arcs.push((cost(*u, &frontier.targets[t]), i, t));
+        }
+    }
+    arcs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then((a.1, a.2).cmp(&(b.1, b.2))));
+
+    let mut demand = frontier.targets.iter().map(|t| strength(t.loc) + 1).collect::<Vec<_>>();
+    let mut assigned: Vec<Option<usize>> = vec![None; units.len()];
+    let mut order = Vec::with_capacity(units.len());
+    for &(_, i, t) in &arcs {
+        if assigned[i].is_none() && demand[t] > 0 {
+            assigned[i] = Some(t);
+            demand[t] = demand[t].saturating_sub(strength(units[i]));
+            order.push(i);
+        }
+    }
+    for (i, a) in assigned.iter_mut().enumerate() {
+        if a.is_none() {
+            // A unit's arcs are in cost order, so the first is its cheapest.
+            *a = arcs.iter().find(|a| a.1 == i).map(|a| a.2);
+            order.push(i);
+        }
+    }
+
+    // What each site will hold, everything of ours staying put until it's
+    // given a step.
+    let load_of = |site: &Site| if site.owner == my_id { site.strength as u16 } else { 0 };
+    let mut load = map.contents.iter().map(|row| row.iter().map(load_of).collect::<Vec<_>>()).collect::<Vec<_>>();
+    for i in order {
+        let (u, s) = (units[i], strength(units[i]));
+        let target = frontier.targets[assigned[i].unwrap()].loc;
+        let here = statics.distance(u, target);
+        let step = CARDINALS.iter().cloned()
+            .filter(|d| statics.distance(map.get_location(u, *d), target) < here)
+            .map(|d| (d, map.get_location(u, d)))
+            .filter(|&(_, n)| load[n.y as usize][n.x as usize] + s <= CAP)
+            .min_by_key(|&(_, n)| load[n.y as usize][n.x as usize]);
+        if let Some((_, n)) = step {
+            load[u.y as usize][u.x as usize] -= s;
+            load[n.y as usize][n.x as usize] += s;
+        }
+        routes.insert(u, step.map_or(STILL, |(d, _)| d));
+    }
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+
+    #[test]
+    fn reinforcements_spread_over_targets() {
+        // Both interior units are closest to the weak site to the west, but it
+        // only needs one of them.
+        let map = scenario::parse("
+            0:200 1:10 1:10 1:10 1:10 1:10 0:40
+            0:5   1:10 1:60 1:60 1:10 1:10 0:40
+            0:200 1:10 1:10 1:10 1:10 1:10 0:40
+        ").unwrap();
+        let statics = MapStatics::new(&map, 1);
+        let frontier = Frontier::new(&map, 1, &statics);
+        let units = [Location { x: 2, y: 1 }, Location { x: 3, y: 1 }];
+        let routes = route(&map, 1, &frontier, &statics, &units);
+        assert_eq!((routes[&units[0]], routes[&units[1]]), (WEST, EAST));
+    }
+
+    #[test]
+    fn steps_stay_under_the_cap() {
+        let map = scenario::parse("
+            1:250 1:200 1:100 0:5 1:250
+        ").unwrap();
+        let statics = MapStatics::new(&map, 1);
+        let frontier = Frontier::new(&map, 1, &statics);
+        let units = [Location { x: 1, y: 0 }, Location { x: 2, y: 0 }];
+        // The 200 can't join the 100 on its way east while the 100 stays.
+        let routes = route(&map, 1, &frontier, &statics, &units[..1]);
+        assert_eq!(routes[&units[0]], STILL);
+        // It can once the 100 moves on.
+        let routes = route(&map, 1, &frontier, &statics, &units);
+        assert_eq!((routes[&units[0]], routes[&units[1]]), (EAST, EAST));
+    }
+}
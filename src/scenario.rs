@@ -0,0 +1,131 @@
+// A plain text format for maps, so positions can be written and read by people.
+//
+// A grid of owner:strength tokens, with . for an empty neutral site, then an
+// optional blank line and a grid of productions. Without the production grid
+// every site produces 1. Lines starting with # are comments.
+//
+//   # Two of ours can take the 2:120 between them
+//   .     1:70  .
+//   1:70  2:120 .
+//   .     .     .
+//
+//   1 1 1
+//   1 3 1
+//   1 1 1
+
+use hlt::types::*;
+use std::fmt;
+
+fn parse_site(token: &str) -> Result<Site, String> {
+    if token == "." {
+        return Ok(Site { owner: 0, strength: 0, production: 1 })
+    }
+    let mut parts = token.splitn(2, ':');
+    let owner = parts.next().unwrap_or("").parse::<u8>();
+    let strength = parts.next().unwrap_or("").parse::<u8>();
+    match (owner, strength) {
+        (Ok(owner), Ok(strength)) => Ok(Site { owner, strength, production: 1 }),
+        _ => Err(format!("bad site '{}', expected owner:strength or .", token)),
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn parse(s: &str) -> Result<GameMap, String> {
+    let mut grids: Vec<Vec<Vec<&str>>> = vec![Vec::new()];
+    for line in s.lines().map(|l| l.trim()).filter(|l| !l.starts_with('#')) {
+        if line.is_empty() {
+            if !grids.last().unwrap().is_empty() {
+                grids.push(Vec::new());
+            }
+        } else {
+            grids.last_mut().unwrap().push(line.split_whitespace().collect());
+        }
+    }
+    grids.retain(|g| !g.is_empty());
+    if grids.is_empty() || grids.len() > 2 {
+        return Err(format!("expected a site grid and an optional production grid, found {} grids", grids.len()))
+    }
+
+    let sites = &grids[0];
+    let width = sites[0].len();
+    if sites.iter().any(|row| row.len() != width) {
+        return Err("site rows have different lengths".to_string())
+    }
+    let mut contents = Vec::new();
+    for row in sites {
+        contents.push(row.iter().map(|t| parse_site(t)).collect::<Result<Vec<_>, _>>()?);
+    }
+
+    if let Some(productions) = grids.get(1) {
+        if productions.len() != contents.len() || productions.iter().any(|row| row.len() != width) {
+            return Err("production grid doesn't match the site grid".to_string())
+        }
+        for (row, production_row) in contents.iter_mut().zip(productions) {
+            for (site, token) in row.iter_mut().zip(production_row) {
+                site.production = token.parse().map_err(|_| format!("bad production '{}'", token))?;
+            }
+        }
+    }
+
+    Ok(GameMap { width: width as u16, height: contents.len() as u16, contents })
+}
+
+// Prints the map in the format parse reads, columns lined up.
+impl fmt::Display for GameMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let token = |site: &Site| {
+            if site.owner == 0 && site.strength == 0 { ".".to_string() } else { format!("{}:{}", site.owner, site.strength) }
+        };
+        let site_width = self.contents.iter().flat_map(|row| row.iter().map(|s| token(s).len())).max().unwrap_or(1);
+        let production_width = self.contents.iter().flat_map(|row| row.iter().map(|s| s.production.to_string().len())).max().unwrap_or(1);
+        for row in &self.contents {
+            let line = row.iter().map(|s| format!("{:w$}", token(s), w = site_width)).collect::<Vec<_>>();
+            writeln!(f, "{}", line.join(" ").trim_end())?;
+        }
+        writeln!(f)?;
+        for row in &self.contents {
+            let line = row.iter().map(|s| format!("{:w$}", s.production, w = production_width)).collect::<Vec<_>>();
+            writeln!(f, "{}", line.join(" ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testmaps::generated_map;
+
+    #[test]
+    fn display_round_trips_through_parse() {
+        for seed in 0..20 {
+            let map = generated_map(seed);
+            let parsed = parse(&map.to_string()).unwrap();
+            assert_eq!((parsed.width, parsed.height), (map.width, map.height));
+            assert_eq!(parsed.contents, map.contents, "map {} changed:\n{}", seed, map);
+        }
+    }
+
+    #[test]
+    fn parse_reads_sites_and_productions() {
+        let map = parse("
+            # comment
+            .    1:70
+            2:12 .
+
+            1 3
+            4 1
+        ").unwrap();
+        assert_eq!((map.width, map.height), (2, 2));
+        assert_eq!(map.contents[0][1], Site { owner: 1, strength: 70, production: 3 });
+        assert_eq!(map.contents[1][0], Site { owner: 2, strength: 12, production: 4 });
+        assert_eq!(map.contents[0][0], Site { owner: 0, strength: 0, production: 1 });
+    }
+
+    #[test]
+    fn parse_rejects_ragged_grids() {
+        assert!(parse(". .\n.").is_err());
+        assert!(parse(". .\n. .\n\n1 1\n1").is_err());
+        assert!(parse("1:x .").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+// Weights for the linear score get_best_move_simple gives each candidate move,
+// one per name in FEATURES, see MoveFeatures::values for what they measure.
+//
+// The defaults make the score behave like the old filters: anything a filter
+// would have thrown out scores far below STILL, and of the rest the weakest
+// target wins, then the most productive. A weights file overrides some or all
+// of them, one "name value" per line with # comments:
+//
+//   # Rather take enemy sites than neutral ones
+//   enemy 2.5
+//   overkill 4
+
+use std::env;
+use std::fs;
+
+pub const FEATURE_COUNT: usize = 21;
+
+pub const FEATURES: [&str; FEATURE_COUNT] = [
+    "bias", // Always 1
+    "still", // 1 for staying STILL
+    "friendly", // Moving onto our own site
+    "enemy", // Moving onto an enemy's site
+    "neutral", // Moving onto a neutral site
+    "not_closest_border", // Another direction reaches the border sooner
+    "distance", // Sites to the border this way, as a share of the map width
+    "strength_us", // Strength of the unit moving, out of 255
+    "strength_them", // Strength of the target site, out of 255
+    "production_us", // Production of the site we leave, out of 32
+    "production_them", // Production of the target site, out of 32
+    "adjacent_strength_us", // Our strength next to the target, out of 255
+    "assigned_strength", // Strength already sent to the target, out of 255
+    "weak", // The unit has less than five turns of its own production
+    "losing_battle", // The target has at least our strength after it produces
+    "over_merge_cap", // Merging with our own site would waste more than a little
+    "over_assigned_cap", // The target already gets too much of our strength
+    "captures", // We'd take the target, counting no help and no enemy moves
+    "strength_lost", // Strength spent taking the target, out of 255
+    "overkill", // Damage done to enemies next to the target, out of 255
+    "merge_waste", // Strength lost to the cap merging with our own site, out of 255
+];
+
+// Where each feature sits in FEATURES and in MoveFeatures::values.
+pub const BIAS: usize = 0;
+pub const STAY: usize = 1;
+pub const FRIENDLY: usize = 2;
+pub const ENEMY: usize = 3;
+pub const NEUTRAL: usize = 4;
+pub const NOT_CLOSEST_BORDER: usize = 5;
+pub const DISTANCE: usize = 6;
+pub const STRENGTH_US: usize = 7;
+pub const STRENGTH_THEM: usize = 8;
+pub const PRODUCTION_US: usize = 9;
+pub const PRODUCTION_THEM: usize = 10;
+pub const ADJACENT_STRENGTH_US: usize = 11;
+pub const ASSIGNED_STRENGTH: usize = 12;
+pub const WEAK: usize = 13;
+pub const LOSING_BATTLE: usize = 14;
+pub const OVER_MERGE_CAP: usize = 15;
+pub const OVER_ASSIGNED_CAP: usize = 16;
+pub const CAPTURES: usize = 17;
+pub const STRENGTH_LOST: usize = 18;
+pub const OVERKILL: usize = 19;
+pub const MERGE_WASTE: usize = 20;
+
+pub struct Weights([f32; FEATURE_COUNT]);
+
+impl Default for Weights {
+    fn default() -> Weights {
+        let mut w = [0.0; FEATURE_COUNT];
+        w[BIAS] = 10.0;
+        w[STAY] = -10.0;
+        w[STRENGTH_THEM] = -5.0;
+        w[PRODUCTION_THEM] = 0.01;
+        for &rule in &[NOT_CLOSEST_BORDER, WEAK, LOSING_BATTLE, OVER_MERGE_CAP, OVER_ASSIGNED_CAP] {
+            w[rule] = -1000.0;
+        }
+        Weights(w)
+    }
+}
+
+impl Weights {
+    pub fn parse(s: &str) -> Result<Weights, String> {
+        let mut weights = Weights::default();
+        for line in s.lines().map(|l| l.split('#').next().unwrap().trim()).filter(|l| !l.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let (name, value) = (parts.next().unwrap(), parts.next());
+            let i = FEATURES.iter().position(|f| *f == name).ok_or(format!("unknown feature '{}'", name))?;
+            weights.0[i] = match (value.map(|v| v.parse()), parts.next()) {
+                (Some(Ok(v)), None) => v,
+                _ => return Err(format!("expected 'name value', found '{}'", line)),
+            };
+        }
+        Ok(weights)
+    }
+
+    // The weights in A2I_WEIGHTS, or weights.txt if there is one, otherwise the
+    // defaults. Bad weights are reported and the defaults used instead.
+    pub fn load() -> Weights {
+        let path = env::var("A2I_WEIGHTS").ok();
+        let text = match fs::read_to_string(path.as_ref().map_or("weights.txt", |p| p.as_str())) {
+            Ok(text) => text,
+            Err(e) => {
+                if let Some(p) = path {
+                    eprintln!("can't read weights {}: {}", p, e);
+                }
+                return Weights::default()
+            },
+        };
+        Weights::parse(&text).unwrap_or_else(|e| {
+            eprintln!("bad weights: {}", e);
+            Weights::default()
+        })
+    }
+
+    pub fn score(&self, values: &[f32; FEATURE_COUNT]) -> f32 {
+        self.0.iter().zip(values.iter()).map(|(w, v)| w * v).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices_match_names() {
+        let indices = [
+            (BIAS, "bias"), (STAY, "still"), (FRIENDLY, "friendly"), (ENEMY, "enemy"), (NEUTRAL, "neutral"),
+            (NOT_CLOSEST_BORDER, "not_closest_border"), (DISTANCE, "distance"), (STRENGTH_US, "strength_us"),
+            (STRENGTH_THEM, "strength_them"), (PRODUCTION_US, "production_us"), (PRODUCTION_THEM, "production_them"),
+            (ADJACENT_STRENGTH_US, "adjacent_strength_us"), (ASSIGNED_STRENGTH, "assigned_strength"), (WEAK, "weak"),
+            (LOSING_BATTLE, "losing_battle"), (OVER_MERGE_CAP, "over_merge_cap"), (OVER_ASSIGNED_CAP, "over_assigned_cap"),
+            (CAPTURES, "captures"), (STRENGTH_LOST, "strength_lost"), (OVERKILL, "overkill"), (MERGE_WASTE, "merge_waste"),
+        ];
+        assert_eq!(indices.len(), FEATURE_COUNT);
+        for &(i, name) in &indices {
+            assert_eq!(FEATURES[i], name);
+        }
+    }
+
+    #[test]
+    fn parse_overrides_defaults() {
+        let weights = Weights::parse("# tuned\nenemy 2.5\n\n  overkill 4 # more\n").unwrap();
+        let weight = |name| weights.0[FEATURES.iter().position(|f| *f == name).unwrap()];
+        assert_eq!((weight("enemy"), weight("overkill"), weight("bias")), (2.5, 4.0, 10.0));
+        assert!(Weights::parse("enemies 1").is_err());
+        assert!(Weights::parse("enemy").is_err());
+        assert!(Weights::parse("enemy x").is_err());
+        assert!(Weights::parse("enemy 1 2").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use hlt::types::*;
+
+// Result of applying one turn of moves to a map.
+pub struct Prediction {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub map: GameMap,
+    pub wasted: u32, // Strength of ours lost to the 255 cap, from merges and production
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub captures: Vec<Location>, // Sites we did not own before the turn but do after
+}
+
+// Per player strength of the pieces standing on each site once moves are applied.
+// A piece of None means the player has nothing there.
+struct Pieces {
+    width: u16,
+    by_owner: Vec<Vec<Option<u16>>>,
+}
+
+impl Pieces {
+    fn index(&self, l: Location) -> usize {
+        l.y as usize * self.width as usize + l.x as usize
+    }
+
+    fn add(&mut self, owner: u8, l: Location, strength: u16) {
+        let i = self.index(l);
+        let piece = &mut self.by_owner[owner as usize][i];
+        *piece = Some(piece.unwrap_or(0) + strength);
+    }
+
+    fn get(&self, owner: u8, l: Location) -> Option<u16> {
+        self.by_owner[owner as usize][self.index(l)]
+    }
+}
+
+// Applies a move set for my_id to the map under Halite rules, assuming every
+// other player keeps all of its pieces still. Sites without an entry in moves
+// stay still, and moves for sites we don't own are ignored like the engine does.
+pub fn simulate(map: &GameMap, my_id: u8, moves: &MoveSet) -> Prediction {
+    let mut max_owner = my_id;
+    for row in &map.contents {
+        for site in row {
+            max_owner = max_owner.max(site.owner);
+        }
+    }
+    let cells = map.width as usize * map.height as usize;
+    let mut pieces = Pieces {
+        width: map.width,
+        by_owner: vec![vec![None; cells]; max_owner as usize + 1],
+    };
+
+    // Production for pieces that stay, then movement. Pieces leave a zero
+    // strength piece behind so vacated territory stays ours.
+    for a in 0..map.height {
+        for b in 0..map.width {
+            let l = Location { x: b, y: a };
+            let site = map.get_site_ref(l, STILL);
+            if site.owner == 0 {
+                pieces.add(0, l, site.strength as u16);
+                continue
+            }
+            let d = if site.owner == my_id { *moves.get(&l).unwrap_or(&STILL) } else { STILL };
+            if d == STILL {
+                pieces.add(site.owner, l, site.strength as u16 + site.production as u16);
+            } else {
+                pieces.add(site.owner, map.get_location(l, d), site.strength as u16);
+                pieces.add(site.owner, l, 0);
+            }
+        }
+    }
+
+    // Cap every player's merged pieces, remembering how much of ours was lost.
+    let mut wasted = 0;
+    for owner in 1..pieces.by_owner.len() {
+        for piece in pieces.by_owner[owner].iter_mut() {
+            if let Some(s) = *piece {
+                if s > 255 {
+                    if owner as u8 == my_id {
+                        wasted += s as u32 - 255;
+                    }
+                    *piece = Some(255);
+                }
+            }
+        }
+    }
+
+    // Damage is dealt simultaneously. Players hit other players on the same
+    // and adjacent sites, while neutral strength only fights on its own site.
+    let mut damage = vec![vec![0u32; cells]; pieces.by_owner.len()];
+    for a in 0..map.height {
+        for b in 0..map.width {
+            let l = Location { x: b, y: a };
+            let i = pieces.index(l);
+            for attacker in 0..pieces.by_owner.len() {
+                let strength = match pieces.by_owner[attacker][i] {
+                    Some(s) if s > 0 => s as u32,
+                    _ => continue,
+                };
+                for (defender, hits) in damage.iter_mut().enumerate() {
+                    if defender == attacker {
+                        continue
+                    }
+                    if pieces.by_owner[defender][i].is_some() {
+                        hits[i] += strength;
+                    }
+                    if attacker == 0 || defender == 0 {
+                        continue
+                    }
+                    for d in &CARDINALS {
+                        let adj = map.get_location(l, *d);
+                        let j = pieces.index(adj);
+                        if pieces.by_owner[defender][j].is_some() {
+                            hits[j] += strength;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut result = map.clone();
+    let mut captures = Vec::new();
+    for a in 0..map.height {
+        for b in 0..map.width {
+            let l = Location { x: b, y: a };
+            let i = pieces.index(l);
+            let mut survivor = (0, 0);
+            for (owner, hits) in damage.iter().enumerate() {
+                if let Some(s) = pieces.get(owner as u8, l) {
+                    let hit = hits[i];
+                    if hit == 0 || hit < s as u32 {
+                        survivor = (owner as u8, s - hit as u16);
+                    }
+                }
+            }
+            let site = result.get_site(l, STILL);
+            if survivor.0 == my_id && site.owner != my_id {
+                captures.push(l);
+            }
+            site.owner = survivor.0;
+            site.strength = survivor.1 as u8;
+        }
+    }
+
+    Prediction { map: result, wasted, captures }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+
+    fn at(x: u16, y: u16) -> Location {
+        Location { x, y }
+    }
+
+    fn site(p: &Prediction, l: Location) -> (u8, u8) {
+        let s = p.map.get_site_ref(l, STILL);
+        (s.owner, s.strength)
+    }
+
+    #[test]
+    fn neutrals_only_fight_on_their_own_site() {
+        let map = scenario::parse("
+            1:50 0:30 .    .    .
+        ").unwrap();
+        let p = simulate(&map, 1, &MoveSet::new());
+        assert_eq!((site(&p, at(0, 0)), site(&p, at(1, 0))), ((1, 51), (0, 30)));
+        assert!(p.captures.is_empty());
+    }
+
+    #[test]
+    fn overkill_hits_enemies_next_to_the_target() {
+        // The 100 takes 20 from the neutral and 31 from the enemy beside it, and
+        // hits the enemy with all 100.
+        let map = scenario::parse("
+            .    .     .    .    .
+            .    1:100 0:20 2:30 .
+            .    .     .    .    .
+        ").unwrap();
+        let p = simulate(&map, 1, &vec![(at(1, 1), EAST)].into_iter().collect());
+        assert_eq!(site(&p, at(2, 1)), (1, 49));
+        assert_eq!(site(&p, at(3, 1)), (0, 0));
+        assert_eq!(p.captures, vec![at(2, 1)]);
+    }
+
+    #[test]
+    fn only_still_pieces_produce_and_movers_leave_an_empty_site() {
+        let map = scenario::parse("
+            1:10 1:10 .    .    .
+
+            3    5    1    1    1
+        ").unwrap();
+        let p = simulate(&map, 1, &vec![(at(0, 0), EAST)].into_iter().collect());
+        assert_eq!((site(&p, at(0, 0)), site(&p, at(1, 0))), ((1, 0), (1, 25)));
+        assert_eq!(p.wasted, 0);
+    }
+
+    #[test]
+    fn merges_are_capped_before_combat() {
+        let map = scenario::parse("
+            .    1:200 0:100 1:200 .
+        ").unwrap();
+        let moves = vec![(at(1, 0), EAST), (at(3, 0), WEST)].into_iter().collect();
+        let p = simulate(&map, 1, &moves);
+        assert_eq!(site(&p, at(2, 0)), (1, 155));
+        assert_eq!(p.wasted, 145);
+    }
+
+    #[test]
+    fn moves_for_other_players_are_ignored() {
+        let map = scenario::parse("
+            2:50 0:10 1:5  .    .
+        ").unwrap();
+        let p = simulate(&map, 1, &vec![(at(0, 0), EAST)].into_iter().collect());
+        assert_eq!((site(&p, at(0, 0)), site(&p, at(1, 0))), ((2, 51), (0, 10)));
+    }
+}
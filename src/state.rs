@@ -0,0 +1,154 @@
+// What the bot carries from one frame to the next: the turn count, the last
+// frame we moved in and what we sent, when each of our sites became ours,
+// ongoing convergence plans, and what we've seen the enemies do.
+//
+// main calls begin_turn once a frame parses, end_turn once moves are sent and
+// lost_frame when a frame can't be trusted. Strategies read the rest.
+
+use converge::Schedule;
+use hlt::types::*;
+
+// What one enemy has done so far, counted from frame to frame.
+#[derive(Default, Clone, Copy, Debug)]
+#[cfg_attr(not(feature = "trace"), allow(dead_code))]
+pub struct EnemyStats {
+    pub sites: u32, // Owned this frame
+    pub strength: u32, // Total this frame
+    pub moved: u32, // Pieces seen leaving their site, over the game
+    pub stayed: u32, // Pieces seen staying put, over the game
+    pub captures: u32, // Sites taken from anyone, over the game
+}
+
+#[cfg_attr(not(feature = "trace"), allow(dead_code))]
+impl EnemyStats {
+    // How often a piece of theirs moves rather than staying, 0 before we've seen any.
+    pub fn move_rate(&self) -> f32 {
+        self.moved as f32 / (self.moved + self.stayed).max(1) as f32
+    }
+}
+
+pub struct BotState {
+    my_id: u8,
+    pub turn: u32, // Frames seen, this one included
+    previous: Option<(GameMap, MoveSet)>, // The last frame we moved in, and our moves
+    captured: Vec<Vec<Option<u32>>>, // Turn each of our sites became ours, by [y][x]
+    pub schedule: Schedule,
+    enemies: Vec<EnemyStats>, // By player, ours and neutral's left empty
+}
+
+#[cfg_attr(not(feature = "trace"), allow(dead_code))]
+impl BotState {
+    pub fn new(map: &GameMap, my_id: u8) -> BotState {
+        let players = map.contents.iter().flat_map(|row| row.iter().map(|s| s.owner)).max().unwrap_or(0);
+        BotState {
+            my_id,
+            turn: 0,
+            previous: None,
+            captured: map.contents.iter()
+                .map(|row| row.iter().map(|s| if s.owner == my_id { Some(0) } else { None }).collect())
+                .collect(),
+            schedule: Schedule::default(),
+            enemies: vec![EnemyStats::default(); players.max(my_id) as usize + 1],
+        }
+    }
+
+    // Counts the frame and updates capture turns and enemy stats against the
+    // last frame we saw.
+    pub fn begin_turn(&mut self, map: &GameMap) {
+        self.turn += 1;
+        for e in &mut self.enemies {
+            e.sites = 0;
+            e.strength = 0;
+        }
+        for (y, row) in map.contents.iter().enumerate() {
+            for (x, site) in row.iter().enumerate() {
+                let before = self.previous.as_ref().map(|p| p.0.contents[y][x]);
+                if site.owner == self.my_id {
+                    if self.captured[y][x].is_none() {
+                        self.captured[y][x] = Some(self.turn);
+                    }
+                } else {
+                    self.captured[y][x] = None;
+                }
+                if site.owner == 0 || site.owner == self.my_id || site.owner as usize >= self.enemies.len() {
+                    continue
+                }
+                let e = &mut self.enemies[site.owner as usize];
+                e.sites += 1;
+                e.strength += site.strength as u32;
+                match before {
+                    Some(b) if b.owner != site.owner => e.captures += 1,
+                    Some(b) if b.strength > 0 && site.strength == 0 => e.moved += 1,
+                    Some(b) if b.strength > 0 => e.stayed += 1,
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    pub fn end_turn(&mut self, map: &GameMap, moves: &MoveSet) {
+        self.previous = Some((map.clone(), moves.clone()));
+    }
+
+    // Nothing from the last frame can be compared with the next, and plans that
+    // count turns have lost one.
+    pub fn lost_frame(&mut self) {
+        self.turn += 1;
+        self.previous = None;
+        self.schedule.clear();
+    }
+
+    pub fn previous(&self) -> Option<(&GameMap, &MoveSet)> {
+        self.previous.as_ref().map(|p| (&p.0, &p.1))
+    }
+
+    // The turn l became ours, 0 for where we started. None if it isn't ours.
+    pub fn captured_at(&self, l: Location) -> Option<u32> {
+        self.captured[l.y as usize][l.x as usize]
+    }
+
+    // The enemies seen so far with what they've done, by player.
+    pub fn enemies(&self) -> Vec<(u8, &EnemyStats)> {
+        self.enemies.iter().enumerate()
+            .filter(|&(p, e)| p != 0 && p != self.my_id as usize && (e.sites > 0 || e.moved + e.stayed + e.captures > 0))
+            .map(|(p, e)| (p as u8, e))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+
+    #[test]
+    fn tracks_captures_and_enemies() {
+        let start = scenario::parse("
+            1:50 0:10 .    2:40
+            .    .    .    2:30
+        ").unwrap();
+        let next = scenario::parse("
+            1:0  1:40 2:0  2:41
+            .    .    .    2:0
+        ").unwrap();
+        let mut state = BotState::new(&start, 1);
+        state.begin_turn(&start);
+        state.end_turn(&start, &vec![(Location { x: 0, y: 0 }, EAST)].into_iter().collect());
+        state.begin_turn(&next);
+        assert_eq!(state.turn, 2);
+        assert_eq!(state.captured_at(Location { x: 0, y: 0 }), Some(0));
+        assert_eq!(state.captured_at(Location { x: 1, y: 0 }), Some(2));
+        assert_eq!(state.captured_at(Location { x: 2, y: 0 }), None);
+        assert_eq!(state.previous().unwrap().1[&Location { x: 0, y: 0 }], EAST);
+
+        let enemies = state.enemies();
+        assert_eq!(enemies.len(), 1);
+        let (player, e) = enemies[0];
+        assert_eq!((player, e.sites, e.strength, e.captures, e.moved, e.stayed), (2, 3, 41, 1, 1, 1));
+        assert_eq!(e.move_rate(), 0.5);
+
+        state.lost_frame();
+        assert!(state.previous().is_none());
+        assert_eq!(state.turn, 3);
+    }
+}
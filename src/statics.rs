@@ -0,0 +1,102 @@
+use hlt::types::*;
+
+// How far production is spread when looking for clusters.
+const CLUSTER_RADIUS: i32 = 4;
+
+// Everything that only depends on the production map and the starting
+// positions, worked out once during the init window and used every frame.
+pub struct MapStatics {
+    pub width: u16,
+    pub height: u16,
+    dx: Vec<u16>, // Wrapped distance for each difference in x
+    dy: Vec<u16>, // Wrapped distance for each difference in y
+    cluster_production: Vec<Vec<f32>>, // Production averaged over CLUSTER_RADIUS, nearer sites counting more, by [y][x]
+    #[cfg_attr(not(feature = "trace"), allow(dead_code))]
+    pub starts: Vec<(u8, Location)>, // Where every player, us included, started
+    home: Option<Location>, // Where we started
+    start_distance: Vec<Vec<u16>>, // Distance to the nearest enemy start, by [y][x]
+}
+
+impl MapStatics {
+    pub fn new(map: &GameMap, my_id: u8) -> MapStatics {
+        let axis = |size: u16| (0..size).map(|d| d.min(size - d)).collect::<Vec<_>>();
+        let mut statics = MapStatics {
+            width: map.width,
+            height: map.height,
+            dx: axis(map.width),
+            dy: axis(map.height),
+            cluster_production: Vec::new(),
+            starts: Vec::new(),
+            home: None,
+            start_distance: Vec::new(),
+        };
+
+        statics.cluster_production = statics.locations()
+            .map(|l| {
+                let (mut total, mut weight) = (0f32, 0f32);
+                for oy in -CLUSTER_RADIUS..CLUSTER_RADIUS + 1 {
+                    for ox in -CLUSTER_RADIUS..CLUSTER_RADIUS + 1 {
+                        let d = ox.abs() + oy.abs();
+                        if d <= CLUSTER_RADIUS {
+                            let site = map.get_site_ref(map.get_offset(l, ox, oy), STILL);
+                            total += site.production as f32 / (1 + d) as f32;
+                            weight += 1.0 / (1 + d) as f32;
+                        }
+                    }
+                }
+                total / weight
+            })
+            .collect::<Vec<_>>()
+            .chunks(map.width as usize)
+            .map(|row| row.to_vec())
+            .collect();
+
+        for l in statics.locations() {
+            let owner = map.get_site_ref(l, STILL).owner;
+            if owner != 0 && !statics.starts.iter().any(|&(o, _)| o == owner) {
+                statics.starts.push((owner, l));
+            }
+        }
+        statics.starts.sort();
+        statics.home = statics.starts.iter().find(|&&(o, _)| o == my_id).map(|&(_, l)| l);
+
+        let enemy_starts = statics.starts.iter()
+            .filter(|&&(o, _)| o != my_id)
+            .map(|&(_, l)| l)
+            .collect::<Vec<_>>();
+        statics.start_distance = (0..map.height)
+            .map(|y| (0..map.width)
+                .map(|x| enemy_starts.iter()
+                    .map(|s| statics.distance(Location { x, y }, *s))
+                    .min()
+                    .unwrap_or(u16::MAX))
+                .collect())
+            .collect();
+
+        statics
+    }
+
+    // Same as GameMap::get_distance, but from the lookup tables.
+    pub fn distance(&self, l1: Location, l2: Location) -> u16 {
+        let dx = (l1.x as i32 - l2.x as i32).unsigned_abs() as usize;
+        let dy = (l1.y as i32 - l2.y as i32).unsigned_abs() as usize;
+        self.dx[dx] + self.dy[dy]
+    }
+
+    pub fn cluster_production(&self, l: Location) -> f32 {
+        self.cluster_production[l.y as usize][l.x as usize]
+    }
+
+    // Whether an enemy started nearer l than we did, so it's likely fought over.
+    pub fn contested(&self, l: Location) -> bool {
+        match self.home {
+            Some(home) => self.start_distance[l.y as usize][l.x as usize] < self.distance(l, home),
+            None => false,
+        }
+    }
+
+    fn locations(&self) -> impl Iterator<Item = Location> {
+        let (width, height) = (self.width, self.height);
+        (0..height).flat_map(move |y| (0..width).map(move |x| Location { x, y }))
+    }
+}
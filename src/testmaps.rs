@@ -0,0 +1,56 @@
+// Maps for tests, generated from a seed so every run sees the same ones.
+
+use hlt::types::*;
+
+// xorshift64, which is plenty for making up maps.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: u32) -> u32 {
+        (self.next() % n as u64) as u32
+    }
+}
+
+// A map between 10x10 and 50x50 with two to four players, each owning a blob
+// of sites around its start. We are always player 1.
+pub fn generated_map(seed: u64) -> GameMap {
+    let mut rng = Rng::new(seed);
+    let width = 10 + rng.below(41) as u16;
+    let height = 10 + rng.below(41) as u16;
+    let mut contents = Vec::new();
+    for _ in 0..height {
+        let mut row = Vec::new();
+        for _ in 0..width {
+            row.push(Site { owner: 0, strength: rng.below(256) as u8, production: 1 + rng.below(15) as u8 });
+        }
+        contents.push(row);
+    }
+    let mut map = GameMap { width, height, contents };
+
+    let players = 2 + rng.below(3) as u8;
+    // Players are placed last to first so that we always keep our blob.
+    for owner in (1..players + 1).rev() {
+        let start = Location { x: rng.below(width as u32) as u16, y: rng.below(height as u32) as u16 };
+        let radius = 1 + rng.below(5) as u16;
+        for a in 0..height {
+            for b in 0..width {
+                let l = Location { x: b, y: a };
+                if map.get_distance(start, l) <= radius && rng.below(4) > 0 {
+                    map.get_site(l, STILL).owner = owner;
+                }
+            }
+        }
+    }
+    map
+}
@@ -0,0 +1,474 @@
+// Per-turn trace records, written as one JSON object per line.
+//
+// Only compiled in with the `trace` feature. Submission builds get the empty
+// versions at the bottom of this file, so calls into the tracer cost nothing,
+// except panics which still go to stderr with their frame.
+//
+// A2I_TRACE_PATH sets the file, with {id} and {pid} substituted so several
+// games can run in one directory. Defaults to trace-{id}-{pid}.jsonl.
+// A2I_TRACE_LEVEL is one of off, turn or unit (the default). At turn level
+// the per-unit troop classes, moves and reasons are left out.
+
+use hlt::types::*;
+use state::BotState;
+use statics::MapStatics;
+use validate::RepairStats;
+use waste::{GameWaste, TurnWaste};
+
+#[cfg(feature = "trace")]
+use std::collections::HashMap;
+#[cfg(feature = "trace")]
+use std::env;
+#[cfg(feature = "trace")]
+use std::fs::File;
+#[cfg(feature = "trace")]
+use std::io::{BufWriter, Write};
+#[cfg(feature = "trace")]
+use std::process;
+#[cfg(feature = "trace")]
+use std::time::Instant;
+
+// A filter in get_best_move_simple that removed a candidate move.
+#[derive(Copy, Clone, Debug)]
+pub enum Filter {
+    WeakPiece,
+    NotClosestBorder,
+    LosingBattle,
+    MergeCap,
+    AssignedCap,
+}
+
+// Why a unit ended up with its move. A unit can collect several.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(not(feature = "trace"), allow(dead_code))]
+pub enum Reason {
+    Rejected(u8, Filter), // The candidate direction and the filter that removed it
+    Branch(&'static str), // The strategy branch that decided the move, as Troop.branch
+    Held, // commit_move kept the unit still to stay under the 260 cap
+}
+
+#[cfg(feature = "trace")]
+impl Reason {
+    fn to_json(self) -> String {
+        match self {
+            Reason::Rejected(d, f) => format!("{{\"rejected\":{},\"filter\":\"{:?}\"}}", d, f),
+            Reason::Branch(name) => format!("{{\"branch\":\"{}\"}}", name),
+            Reason::Held => "{\"held\":true}".to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+enum Level {
+    Off,
+    Turn,
+    Unit,
+}
+
+#[cfg(feature = "trace")]
+pub struct Tracer {
+    level: Level,
+    path: String,
+    out: Option<BufWriter<File>>,
+}
+
+#[cfg(feature = "trace")]
+pub struct TurnTrace {
+    turn: u32,
+    start: Instant,
+    strategy: &'static str,
+    classes: HashMap<Location, &'static str>,
+    reasons: HashMap<Location, Vec<Reason>>,
+    waste: Option<TurnWaste>,
+    repairs: Option<RepairStats>,
+    panics: Vec<String>,
+}
+
+// Quotes s as a JSON string.
+#[cfg(feature = "trace")]
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(feature = "trace")]
+impl Tracer {
+    pub fn open(my_id: u8) -> Tracer {
+        let level = match env::var("A2I_TRACE_LEVEL").as_ref().map(|s| s.as_str()) {
+            Ok("off") => Level::Off,
+            Ok("turn") => Level::Turn,
+            _ => Level::Unit,
+        };
+        let path = env::var("A2I_TRACE_PATH")
+            .unwrap_or_else(|_| "trace-{id}-{pid}.jsonl".to_string())
+            .replace("{id}", &my_id.to_string())
+            .replace("{pid}", &process::id().to_string());
+        let out = if level == Level::Off { None } else { File::create(&path).ok().map(BufWriter::new) };
+        Tracer { level, path, out }
+    }
+
+    pub fn begin_turn(&self, turn: u32) -> TurnTrace {
+        TurnTrace::new(turn)
+    }
+
+    pub fn end_turn(&mut self, t: TurnTrace, moves: &MoveSet) {
+        let out = match self.out {
+            Some(ref mut out) => out,
+            None => return,
+        };
+        let elapsed = t.start.elapsed();
+        let mut s = format!("{{\"turn\":{},\"elapsed_ms\":{:.3},\"units\":{},\"strategy\":\"{}\"",
+                            t.turn, elapsed.as_secs() as f64 * 1e3 + elapsed.subsec_nanos() as f64 / 1e6,
+                            moves.len(), t.strategy);
+        if let Some(w) = t.waste {
+            s += &format!(",\"waste\":{{\"merge_capped\":{},\"production_capped\":{},\"combat_lost\":{},\"damage_dealt\":{}}}",
+                          w.merge_capped, w.production_capped, w.combat_lost, w.damage_dealt);
+        }
+        if let Some(r) = t.repairs {
+            s += &format!(",\"repairs\":{{\"missing\":{},\"unowned\":{},\"invalid\":{},\"rerouted\":{},\"held\":{},\"overflowing\":{},\"wasted\":{}}}",
+                          r.missing, r.unowned, r.invalid, r.rerouted, r.held, r.overflowing, r.wasted);
+        }
+        if !t.panics.is_empty() {
+            s += &format!(",\"panics\":[{}]", t.panics.join(","));
+        }
+        if self.level >= Level::Unit {
+            let mut locs = moves.keys().cloned().collect::<Vec<_>>();
+            locs.sort_by_key(|l| (l.y, l.x));
+            let units = locs.iter()
+                .map(|l| {
+                    let troop = match t.classes.get(l) {
+                        Some(class) => format!("\"{}\"", class),
+                        None => "null".to_string(),
+                    };
+                    let reasons = t.reasons.get(l)
+                        .map_or(Vec::new(), |rs| rs.iter().map(|r| r.to_json()).collect());
+                    format!("{{\"x\":{},\"y\":{},\"troop\":{},\"move\":{},\"reasons\":[{}]}}",
+                            l.x, l.y, troop, moves[l], reasons.join(","))
+                })
+                .collect::<Vec<_>>();
+            s += &format!(",\"unit_moves\":[{}]", units.join(","));
+        }
+        s += "}\n";
+        let _ = out.write_all(s.as_bytes()).and_then(|_| out.flush());
+    }
+
+    // The first record, with what was worked out during init.
+    pub fn statics(&mut self, statics: &MapStatics) {
+        let out = match self.out {
+            Some(ref mut out) => out,
+            None => return,
+        };
+        let starts = statics.starts.iter()
+            .map(|&(o, l)| format!("{{\"owner\":{},\"x\":{},\"y\":{}}}", o, l.x, l.y))
+            .collect::<Vec<_>>();
+        let s = format!("{{\"init\":{{\"starts\":[{}]}}}}\n", starts.join(","));
+        let _ = out.write_all(s.as_bytes()).and_then(|_| out.flush());
+    }
+
+    // Kept next to the trace and overwritten every turn, since the engine
+    // kills us without warning at the end of the game.
+    pub fn summary(&self, game: &GameWaste, state: &BotState, map: &GameMap) {
+        if self.level == Level::Off {
+            return
+        }
+        let w = game.total;
+        let ages = map.contents.iter().enumerate()
+            .flat_map(|(y, row)| (0..row.len()).filter_map(move |x| state.captured_at(Location { x: x as u16, y: y as u16 })))
+            .map(|at| (state.turn - at) as f64)
+            .collect::<Vec<_>>();
+        let enemies = state.enemies().iter()
+            .map(|&(p, e)| format!("{{\"player\":{},\"sites\":{},\"strength\":{},\"captures\":{},\"move_rate\":{:.3}}}",
+                                   p, e.sites, e.strength, e.captures, e.move_rate()))
+            .collect::<Vec<_>>();
+        let s = format!("{{\"turns\":{},\"merge_capped\":{},\"production_capped\":{},\"combat_lost\":{},\"damage_dealt\":{},\"territory_age\":{:.1},\"enemies\":[{}]}}\n",
+                        game.turns, w.merge_capped, w.production_capped, w.combat_lost, w.damage_dealt,
+                        ages.iter().sum::<f64>() / ages.len().max(1) as f64, enemies.join(","));
+        if let Ok(mut f) = File::create(format!("{}.summary", self.path)) {
+            let _ = f.write_all(s.as_bytes());
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+impl TurnTrace {
+    // A record that is only written if handed to Tracer::end_turn.
+    pub fn new(turn: u32) -> TurnTrace {
+        TurnTrace {
+            turn,
+            start: Instant::now(),
+            strategy: "",
+            classes: HashMap::new(),
+            reasons: HashMap::new(),
+            waste: None,
+            repairs: None,
+            panics: Vec::new(),
+        }
+    }
+
+    pub fn strategy(&mut self, name: &'static str) {
+        self.strategy = name;
+    }
+
+    pub fn unit(&mut self, l: Location, class: &'static str) {
+        self.classes.insert(l, class);
+    }
+
+    pub fn reason(&mut self, l: Location, r: Reason) {
+        self.reasons.entry(l).or_default().push(r);
+    }
+
+    pub fn waste(&mut self, w: &TurnWaste) {
+        self.waste = Some(*w);
+    }
+
+    pub fn repairs(&mut self, r: &RepairStats) {
+        self.repairs = Some(*r);
+    }
+
+    // Records a panic along with the frame that caused it, so the turn can be
+    // rebuilt without the transcript.
+    pub fn panic(&mut self, during: &'static str, message: String, map: &GameMap) {
+        let mut owners = Vec::new();
+        let mut strengths = Vec::new();
+        for row in &map.contents {
+            for site in row {
+                owners.push(site.owner.to_string());
+                strengths.push(site.strength.to_string());
+            }
+        }
+        self.panics.push(format!("{{\"during\":\"{}\",\"message\":{},\"width\":{},\"height\":{},\"owners\":[{}],\"strengths\":[{}]}}",
+                                 during, json_string(&message), map.width, map.height,
+                                 owners.join(","), strengths.join(",")));
+    }
+}
+
+#[cfg(not(feature = "trace"))]
+pub struct Tracer;
+
+#[cfg(not(feature = "trace"))]
+pub struct TurnTrace {
+    turn: u32,
+}
+
+#[cfg(not(feature = "trace"))]
+impl Tracer {
+    #[inline(always)]
+    pub fn open(_my_id: u8) -> Tracer { Tracer }
+    #[inline(always)]
+    pub fn begin_turn(&self, turn: u32) -> TurnTrace { TurnTrace::new(turn) }
+    #[inline(always)]
+    pub fn end_turn(&mut self, _t: TurnTrace, _moves: &MoveSet) {}
+    #[inline(always)]
+    pub fn statics(&mut self, _statics: &MapStatics) {}
+    #[inline(always)]
+    pub fn summary(&self, _game: &GameWaste, _state: &BotState, _map: &GameMap) {}
+}
+
+#[cfg(not(feature = "trace"))]
+impl TurnTrace {
+    #[inline(always)]
+    pub fn new(turn: u32) -> TurnTrace { TurnTrace { turn } }
+    #[inline(always)]
+    pub fn strategy(&mut self, _name: &'static str) {}
+    #[inline(always)]
+    pub fn unit(&mut self, _l: Location, _class: &'static str) {}
+    #[inline(always)]
+    pub fn reason(&mut self, _l: Location, _r: Reason) {}
+    #[inline(always)]
+    pub fn waste(&mut self, _w: &TurnWaste) {}
+    #[inline(always)]
+    pub fn repairs(&mut self, _r: &RepairStats) {}
+    // The default hook only prints the message, so add which turn and step it
+    // was, and the frame in the scenario format to rebuild it from.
+    pub fn panic(&mut self, during: &'static str, message: String, map: &GameMap) {
+        eprint!("turn {}: {} panicked: {}\n{}", self.turn, during, message, map);
+    }
+}
+
+#[cfg(all(test, feature = "trace"))]
+mod tests {
+    use super::*;
+    use scenario;
+    use std::fs;
+
+    // Just enough JSON to read the trace back.
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Null,
+        Bool(bool),
+        Number(f64),
+        Str(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> Option<&Json> {
+            match *self {
+                Json::Object(ref fields) => fields.iter().find(|f| f.0 == key).map(|f| &f.1),
+                _ => None,
+            }
+        }
+
+        fn at(&self, key: &str) -> &Json {
+            self.get(key).unwrap_or_else(|| panic!("no {} in {:?}", key, self))
+        }
+
+        fn items(&self) -> &[Json] {
+            match *self {
+                Json::Array(ref items) => items,
+                _ => panic!("not an array: {:?}", self),
+            }
+        }
+    }
+
+    fn parse(s: &str) -> Json {
+        let chars = s.trim().chars().collect::<Vec<_>>();
+        let mut pos = 0;
+        let value = parse_value(&chars, &mut pos);
+        assert_eq!(pos, chars.len(), "trailing input in {}", s);
+        value
+    }
+
+    fn parse_value(s: &[char], pos: &mut usize) -> Json {
+        let c = s[*pos];
+        match c {
+            '{' | '[' => {
+                *pos += 1;
+                let (mut fields, mut items) = (Vec::new(), Vec::new());
+                while s[*pos] != if c == '{' { '}' } else { ']' } {
+                    if c == '{' {
+                        let key = match parse_value(s, pos) {
+                            Json::Str(key) => key,
+                            other => panic!("bad key {:?}", other),
+                        };
+                        assert_eq!(s[*pos], ':');
+                        *pos += 1;
+                        fields.push((key, parse_value(s, pos)));
+                    } else {
+                        items.push(parse_value(s, pos));
+                    }
+                    if s[*pos] == ',' {
+                        *pos += 1;
+                    }
+                }
+                *pos += 1;
+                if c == '{' { Json::Object(fields) } else { Json::Array(items) }
+            },
+            '"' => {
+                let mut out = String::new();
+                *pos += 1;
+                while s[*pos] != '"' {
+                    if s[*pos] == '\\' {
+                        *pos += 1;
+                        match s[*pos] {
+                            'n' => out.push('\n'),
+                            'u' => {
+                                let hex = s[*pos + 1..*pos + 5].iter().collect::<String>();
+                                out.push(std::char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap());
+                                *pos += 4;
+                            },
+                            c => out.push(c),
+                        }
+                    } else {
+                        out.push(s[*pos]);
+                    }
+                    *pos += 1;
+                }
+                *pos += 1;
+                Json::Str(out)
+            },
+            _ => {
+                let start = *pos;
+                while *pos < s.len() && !",}]".contains(s[*pos]) {
+                    *pos += 1;
+                }
+                match s[start..*pos].iter().collect::<String>().as_str() {
+                    "null" => Json::Null,
+                    "true" => Json::Bool(true),
+                    "false" => Json::Bool(false),
+                    n => Json::Number(n.parse().unwrap_or_else(|_| panic!("bad value {}", n))),
+                }
+            },
+        }
+    }
+
+    fn text(s: &str) -> Json {
+        Json::Str(s.to_string())
+    }
+
+    #[test]
+    fn turns_are_written_as_json_lines() {
+        let path = env::temp_dir().join(format!("a2i-trace-{{id}}-{}.jsonl", process::id())).to_str().unwrap().to_owned();
+        env::set_var("A2I_TRACE_PATH", &path);
+        env::set_var("A2I_TRACE_LEVEL", "unit");
+        let path = path.replace("{id}", "1");
+
+        let map = scenario::parse("
+            1:50 0:20 2:30 .    .
+        ").unwrap();
+        let mut tracer = Tracer::open(1);
+        tracer.statics(&MapStatics::new(&map, 1));
+        let mut t = tracer.begin_turn(7);
+        t.strategy("troop_strategy");
+        t.unit(Location { x: 0, y: 0 }, "Lance");
+        t.reason(Location { x: 0, y: 0 }, Reason::Rejected(NORTH, Filter::LosingBattle));
+        t.reason(Location { x: 0, y: 0 }, Reason::Branch("Lance.attack"));
+        t.reason(Location { x: 0, y: 0 }, Reason::Held);
+        t.panic("repair", "bad \"move\"\n\tat (0, 0)".to_string(), &map);
+        tracer.end_turn(t, &vec![(Location { x: 0, y: 0 }, EAST)].into_iter().collect());
+        let mut state = BotState::new(&map, 1);
+        state.begin_turn(&map);
+        tracer.summary(&GameWaste::default(), &state, &map);
+
+        let lines = fs::read_to_string(&path).unwrap().lines().map(parse).collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let starts = lines[0].at("init").at("starts").items();
+        assert_eq!((starts.len(), starts[1].at("owner")), (2, &Json::Number(2.0)));
+
+        let turn = &lines[1];
+        assert_eq!((turn.at("turn"), turn.at("units"), turn.at("strategy")), (&Json::Number(7.0), &Json::Number(1.0), &text("troop_strategy")));
+        match *turn.at("elapsed_ms") {
+            Json::Number(ms) => assert!(ms >= 0.0),
+            ref other => panic!("elapsed_ms is {:?}", other),
+        }
+        let unit = &turn.at("unit_moves").items()[0];
+        assert_eq!((unit.at("x"), unit.at("troop"), unit.at("move")), (&Json::Number(0.0), &text("Lance"), &Json::Number(EAST as f64)));
+        assert_eq!(unit.at("reasons").items(), &[
+            Json::Object(vec![("rejected".to_string(), Json::Number(NORTH as f64)), ("filter".to_string(), text("LosingBattle"))]),
+            Json::Object(vec![("branch".to_string(), text("Lance.attack"))]),
+            Json::Object(vec![("held".to_string(), Json::Bool(true))]),
+        ][..]);
+        let panic = &turn.at("panics").items()[0];
+        assert_eq!((panic.at("during"), panic.at("message")), (&text("repair"), &text("bad \"move\"\n\tat (0, 0)")));
+        assert_eq!(panic.at("strengths").items().len(), 5);
+
+        let summary = parse(&fs::read_to_string(format!("{}.summary", path)).unwrap());
+        assert_eq!(summary.at("turns"), &Json::Number(0.0));
+        assert_eq!(summary.at("enemies").items()[0].at("player"), &Json::Number(2.0));
+
+        // Turn level keeps the turn fields but leaves the units out.
+        env::set_var("A2I_TRACE_LEVEL", "turn");
+        let mut tracer = Tracer::open(1);
+        let mut t = tracer.begin_turn(8);
+        t.reason(Location { x: 0, y: 0 }, Reason::Held);
+        tracer.end_turn(t, &vec![(Location { x: 0, y: 0 }, STILL)].into_iter().collect());
+        let line = parse(&fs::read_to_string(&path).unwrap());
+        assert_eq!(line.at("turn"), &Json::Number(8.0));
+        assert!(line.get("unit_moves").is_none());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.summary", path)).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use budget::Budget;
+use hlt::types::*;
+use sim;
+use std::collections::{BTreeMap, BTreeSet};
+
+// How many moves the repair pass had to touch this turn.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct RepairStats {
+    pub missing: u32, // Owned sites without a move, set to STILL
+    pub unowned: u32, // Moves for sites we don't own, dropped
+    pub invalid: u32, // Moves with an unknown direction, set to STILL
+    pub rerouted: u32, // Moves sent elsewhere to avoid a merge above 255
+    pub held: u32, // Moves turned into STILL to avoid a merge above 255
+    pub wasted: u32, // Strength the repaired move set still loses to the cap, only worked out for the trace
+    pub overflowing: u32, // Sites still getting more than 255 from several pieces once we gave up or ran out of time
+}
+
+// Strength our piece at l arrives with if it moves in direction d.
+fn arriving_strength(map: &GameMap, l: Location, d: u8) -> u16 {
+    let site = map.get_site_ref(l, STILL);
+    if d == STILL { site.strength as u16 + site.production as u16 } else { site.strength as u16 }
+}
+
+// Strength and pieces arriving at each destination, and the destinations that
+// several of our pieces merge onto above 255. Kept up to date one move at a
+// time so a fix doesn't have to go over the whole move set again.
+struct Incoming {
+    by_dest: BTreeMap<Location, (u16, Vec<Location>)>,
+    overflows: BTreeSet<Location>,
+}
+
+impl Incoming {
+    fn new(map: &GameMap, moves: &MoveSet) -> Incoming {
+        let mut incoming = Incoming { by_dest: BTreeMap::new(), overflows: BTreeSet::new() };
+        for (l, d) in moves {
+            incoming.add(map, *l, *d);
+        }
+        incoming
+    }
+
+    fn add(&mut self, map: &GameMap, l: Location, d: u8) {
+        let dest = map.get_location(l, d);
+        let entry = self.by_dest.entry(dest).or_insert((0, Vec::new()));
+        entry.0 += arriving_strength(map, l, d);
+        entry.1.push(l);
+        self.update(dest);
+    }
+
+    fn remove(&mut self, map: &GameMap, l: Location, d: u8) {
+        let dest = map.get_location(l, d);
+        let entry = self.by_dest.get_mut(&dest).unwrap();
+        entry.0 -= arriving_strength(map, l, d);
+        entry.1.retain(|from| *from != l);
+        self.update(dest);
+    }
+
+    fn update(&mut self, dest: Location) {
+        let (total, ref from) = self.by_dest[&dest];
+        if total > 255 && from.len() > 1 {
+            self.overflows.insert(dest);
+        } else {
+            self.overflows.remove(&dest);
+        }
+    }
+
+    fn total(&self, dest: Location) -> u16 {
+        self.by_dest.get(&dest).map_or(0, |e| e.0)
+    }
+}
+
+// Makes moves contain exactly one valid move for every site we own, then
+// breaks up merges predicted to exceed 255 by re-routing or holding movers
+// until they're all gone or the budget runs out.
+pub fn repair(map: &GameMap, my_id: u8, moves: &mut MoveSet, budget: &Budget) -> RepairStats {
+    let mut stats = RepairStats::default();
+
+    let before = moves.len();
+    moves.retain(|l, _| map.in_bounds(*l) && map.get_site_ref(*l, STILL).owner == my_id);
+    stats.unowned = (before - moves.len()) as u32;
+    for d in moves.values_mut() {
+        if !DIRECTIONS.contains(d) {
+            *d = STILL;
+            stats.invalid += 1;
+        }
+    }
+    for a in 0..map.height {
+        for b in 0..map.width {
+            let l = Location { x: b, y: a };
+            if map.get_site_ref(l, STILL).owner == my_id && !moves.contains_key(&l) {
+                moves.insert(l, STILL);
+                stats.missing += 1;
+            }
+        }
+    }
+
+    // Every fix changes one mover, but a re-routed piece can cause an overflow
+    // somewhere else and be moved again, so give up after one fix per piece
+    // and count what's left.
+    let mut incoming = Incoming::new(map, moves);
+    for _ in 0..moves.len() {
+        let dest = match incoming.overflows.iter().next() {
+            Some(dest) if !budget.exhausted() => *dest,
+            _ => break,
+        };
+
+        // Only one piece can stay on dest, so there is always a mover to change.
+        // Take the weakest since it is the cheapest to send elsewhere.
+        let mut movers = incoming.by_dest[&dest].1.iter()
+            .cloned()
+            .filter(|l| moves[l] != STILL)
+            .collect::<Vec<_>>();
+        movers.sort_by_key(|l| (map.get_site_ref(*l, STILL).strength, *l));
+        let l = movers[0];
+        let strength = map.get_site_ref(l, STILL).strength;
+
+        let reroute = CARDINALS.iter()
+            .cloned()
+            .filter(|d| *d != moves[&l])
+            .find(|d| {
+                let proposed_loc = map.get_location(l, *d);
+                let proposed = map.get_site_ref(l, *d);
+                let already = incoming.total(proposed_loc);
+                if proposed.owner == my_id {
+                    already + strength as u16 <= 255
+                } else {
+                    already == 0 && strength > proposed.strength
+                }
+            });
+        let d = match reroute {
+            Some(d) => {
+                stats.rerouted += 1;
+                d
+            },
+            None => {
+                stats.held += 1;
+                STILL
+            },
+        };
+        incoming.remove(map, l, moves[&l]);
+        incoming.add(map, l, d);
+        moves.insert(l, d);
+    }
+
+    stats.overflowing = incoming.overflows.len() as u32;
+    if cfg!(feature = "trace") {
+        stats.wasted = sim::simulate(map, my_id, moves).wasted;
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+    use std::time::Duration;
+    use testmaps::{generated_map, Rng};
+
+    fn at(x: u16, y: u16) -> Location {
+        Location { x, y }
+    }
+
+    fn no_limit() -> Budget {
+        Budget::start(Budget::unlimited())
+    }
+
+    #[test]
+    fn every_owned_site_gets_one_valid_move() {
+        let map = scenario::parse("
+            1:10 1:10 2:10 .    .
+        ").unwrap();
+        let mut moves = vec![(at(0, 0), 9), (at(2, 0), EAST), (at(7, 7), NORTH)].into_iter().collect();
+        let stats = repair(&map, 1, &mut moves, &no_limit());
+        assert_eq!(moves, vec![(at(0, 0), STILL), (at(1, 0), STILL)].into_iter().collect());
+        assert_eq!((stats.missing, stats.unowned, stats.invalid), (1, 2, 1));
+    }
+
+    #[test]
+    fn big_merges_are_rerouted_or_held() {
+        // Nowhere else for the first 200 to go, so it stays.
+        let map = scenario::parse("
+            0:255 0:255 0:255 0:255 0:255
+            0:255 1:200 1:10  1:200 0:255
+            0:255 0:255 0:255 0:255 0:255
+        ").unwrap();
+        let mut moves = vec![(at(1, 1), EAST), (at(3, 1), WEST)].into_iter().collect();
+        let stats = repair(&map, 1, &mut moves, &no_limit());
+        assert_eq!((moves[&at(1, 1)], moves[&at(3, 1)]), (STILL, WEST));
+        assert_eq!((stats.rerouted, stats.held, stats.overflowing), (0, 1, 0));
+
+        // With an empty site above it, it takes that instead.
+        let map = scenario::parse("
+            0:255 .     0:255 0:255 0:255
+            0:255 1:200 1:10  1:200 0:255
+            0:255 0:255 0:255 0:255 0:255
+        ").unwrap();
+        let mut moves = vec![(at(1, 1), EAST), (at(3, 1), WEST)].into_iter().collect();
+        let stats = repair(&map, 1, &mut moves, &no_limit());
+        assert_eq!((moves[&at(1, 1)], moves[&at(3, 1)]), (NORTH, WEST));
+        assert_eq!((stats.rerouted, stats.held, stats.overflowing), (1, 0, 0));
+    }
+
+    #[test]
+    fn holding_a_piece_fixes_the_overflow_it_causes() {
+        // The middle 200 can't join the 200 that stays, nor go back west where
+        // a 100 is arriving, so it's held. That overflows its own site with the
+        // 100 following it, which then has to turn back.
+        let map = scenario::parse("
+            0:255 0:255 0:255 0:255 0:255
+            1:100 1:100 1:200 1:200 0:255
+            0:255 0:255 0:255 0:255 0:255
+        ").unwrap();
+        let mut moves = vec![(at(0, 1), EAST), (at(1, 1), EAST), (at(2, 1), EAST), (at(3, 1), STILL)].into_iter().collect();
+        let stats = repair(&map, 1, &mut moves, &no_limit());
+        assert_eq!((moves[&at(1, 1)], moves[&at(2, 1)]), (WEST, STILL));
+        assert_eq!((stats.rerouted, stats.held, stats.overflowing), (1, 1, 0));
+    }
+
+    #[test]
+    fn random_moves_are_repaired_without_overflow() {
+        for seed in 0..50 {
+            let map = generated_map(seed);
+            let mut rng = Rng::new(seed);
+            let mut moves = MoveSet::new();
+            for a in 0..map.height {
+                for b in 0..map.width {
+                    moves.insert(at(b, a), DIRECTIONS[rng.below(5) as usize]);
+                }
+            }
+            let stats = repair(&map, 1, &mut moves, &no_limit());
+            assert_eq!(stats.overflowing, 0, "map {}", seed);
+            assert!(Incoming::new(&map, &moves).overflows.is_empty(), "map {}", seed);
+        }
+    }
+
+    // A full board of 200s with every other column moving onto the next.
+    fn crowded() -> (GameMap, MoveSet) {
+        let row = vec!["1:200"; 50].join(" ");
+        let map = scenario::parse(&vec![row; 50].join("\n")).unwrap();
+        let moves = (0..50).flat_map(|y| (0..50).map(move |x| (at(x, y), if x % 2 == 0 { EAST } else { STILL }))).collect();
+        (map, moves)
+    }
+
+    #[test]
+    fn crowded_boards_are_repaired() {
+        let (map, mut moves) = crowded();
+        let stats = repair(&map, 1, &mut moves, &no_limit());
+        assert_eq!((stats.rerouted + stats.held, stats.overflowing), (1250, 0));
+    }
+
+    #[test]
+    fn repair_stops_when_out_of_time() {
+        let (map, mut moves) = crowded();
+        let stats = repair(&map, 1, &mut moves, &Budget::start(Duration::from_secs(0)));
+        assert_eq!((stats.rerouted, stats.held, stats.overflowing), (0, 0, 1250));
+        assert_eq!(moves.len(), 2500);
+    }
+}
@@ -0,0 +1,167 @@
+use hlt::types::*;
+use std::collections::HashMap;
+use std::fmt;
+
+// Where our strength went between two consecutive frames.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct TurnWaste {
+    pub merge_capped: u32, // Lost when several of our pieces merged above 255
+    pub production_capped: u32, // Production lost by a piece staying at or near 255
+    pub combat_lost: u32, // Our strength destroyed in fights, neutral or enemy
+    pub damage_dealt: u32, // Neutral strength we took, plus enemy strength in reach of our pieces
+}
+
+impl TurnWaste {
+    fn add(&mut self, other: &TurnWaste) {
+        self.merge_capped += other.merge_capped;
+        self.production_capped += other.production_capped;
+        self.combat_lost += other.combat_lost;
+        self.damage_dealt += other.damage_dealt;
+    }
+}
+
+impl fmt::Display for TurnWaste {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "merge_capped={} production_capped={} combat_lost={} damage_dealt={}",
+               self.merge_capped, self.production_capped, self.combat_lost, self.damage_dealt)
+    }
+}
+
+// Measures what happened to our strength going from prev to current, given the
+// moves we sent for prev. Enemy moves are unknown, so damage dealt to enemies
+// is estimated as if they stayed where they were in prev.
+pub fn measure(prev: &GameMap, moves: &MoveSet, current: &GameMap, my_id: u8) -> TurnWaste {
+    let mut waste = TurnWaste::default();
+
+    // Strength of each of our pieces after moving and merging, before combat.
+    let mut arriving: HashMap<Location, (u32, u32)> = HashMap::new();
+    for a in 0..prev.height {
+        for b in 0..prev.width {
+            let l = Location { x: b, y: a };
+            let site = prev.get_site_ref(l, STILL);
+            if site.owner != my_id {
+                continue
+            }
+            let d = *moves.get(&l).unwrap_or(&STILL);
+            let strength = if d == STILL { site.strength as u32 + site.production as u32 } else { site.strength as u32 };
+            let entry = arriving.entry(prev.get_location(l, d)).or_insert((0, 0));
+            entry.0 += strength;
+            entry.1 += 1;
+        }
+    }
+    let mut expected = 0u32;
+    for (l, &(strength, count)) in &arriving {
+        if strength > 255 {
+            if count > 1 {
+                waste.merge_capped += strength - 255;
+            } else {
+                waste.production_capped += strength - 255;
+            }
+        }
+        let strength = strength.min(255);
+        expected += strength;
+
+        let site = prev.get_site_ref(*l, STILL);
+        if site.owner == 0 {
+            waste.damage_dealt += strength.min(site.strength as u32);
+        }
+    }
+
+    let mut actual = 0u32;
+    for a in 0..current.height {
+        for b in 0..current.width {
+            let l = Location { x: b, y: a };
+            let site = current.get_site_ref(l, STILL);
+            if site.owner == my_id {
+                actual += site.strength as u32;
+            }
+            let before = prev.get_site_ref(l, STILL);
+            if before.owner != 0 && before.owner != my_id {
+                let in_reach: u32 = DIRECTIONS.iter()
+                    .map(|d| arriving.get(&prev.get_location(l, *d)).map_or(0, |e| e.0.min(255)))
+                    .sum();
+                waste.damage_dealt += in_reach.min(before.strength as u32 + before.production as u32);
+            }
+        }
+    }
+    waste.combat_lost = expected.saturating_sub(actual);
+    waste
+}
+
+// Running totals over a whole game.
+#[derive(Default)]
+pub struct GameWaste {
+    pub turns: u32,
+    pub total: TurnWaste,
+}
+
+impl GameWaste {
+    pub fn record(&mut self, turn: &TurnWaste) {
+        self.turns += 1;
+        self.total.add(turn);
+    }
+}
+
+impl fmt::Display for GameWaste {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "turns={} {}", self.turns, self.total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+    use sim;
+
+    fn at(x: u16, y: u16) -> Location {
+        Location { x, y }
+    }
+
+    // What measure makes of moves sent for map, with the next frame simulated.
+    fn after(map: &GameMap, moves: Vec<(Location, u8)>) -> TurnWaste {
+        let moves = moves.into_iter().collect();
+        measure(map, &moves, &sim::simulate(map, 1, &moves).map, 1)
+    }
+
+    #[test]
+    fn merges_above_the_cap_are_merge_capped() {
+        let map = scenario::parse("
+            1:200 1:0 1:200 .    .
+
+            1     0   1     1    1
+        ").unwrap();
+        let waste = after(&map, vec![(at(0, 0), EAST), (at(2, 0), WEST)]);
+        assert_eq!((waste.merge_capped, waste.production_capped, waste.combat_lost, waste.damage_dealt), (145, 0, 0, 0));
+    }
+
+    #[test]
+    fn a_full_piece_staying_loses_its_production() {
+        let map = scenario::parse("
+            1:255 .    .    .    .
+
+            5     1    1    1    1
+        ").unwrap();
+        let waste = after(&map, Vec::new());
+        assert_eq!((waste.merge_capped, waste.production_capped, waste.combat_lost), (0, 5, 0));
+    }
+
+    #[test]
+    fn taking_a_neutral_costs_and_deals_its_strength() {
+        let map = scenario::parse("
+            1:50 0:20 .    .    .
+        ").unwrap();
+        let waste = after(&map, vec![(at(0, 0), EAST)]);
+        assert_eq!((waste.merge_capped, waste.combat_lost, waste.damage_dealt), (0, 20, 20));
+    }
+
+    #[test]
+    fn enemies_next_to_us_count_as_damage() {
+        // The enemy is assumed to stay, so all 31 it would have is in reach.
+        let map = scenario::parse("
+            .    1:100 2:30 .    .
+        ").unwrap();
+        let waste = after(&map, Vec::new());
+        assert_eq!((waste.combat_lost, waste.damage_dealt), (31, 31));
+    }
+}
@@ -0,0 +1,163 @@
+// Square windows of sites around a unit, turned and mirrored to one canonical
+// orientation so the eight versions of a position look the same. Used to mine
+// and export replays, and to feed learned policies the same features in game.
+
+use hlt::types::*;
+
+// Values per site in features: ours, an enemy's, strength and production.
+pub const CHANNELS: usize = 4;
+
+// Productions above this all look the same to a policy.
+const MAX_PRODUCTION: f32 = 32.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Orientation {
+    pub mirrored: bool, // Mirrored left to right first
+    pub quarter_turns: u32, // Then turned clockwise this many times
+}
+
+// In the order patterns::compile expands patterns.
+pub const ORIENTATIONS: [Orientation; 8] = [
+    Orientation { mirrored: false, quarter_turns: 0 },
+    Orientation { mirrored: false, quarter_turns: 1 },
+    Orientation { mirrored: false, quarter_turns: 2 },
+    Orientation { mirrored: false, quarter_turns: 3 },
+    Orientation { mirrored: true, quarter_turns: 0 },
+    Orientation { mirrored: true, quarter_turns: 1 },
+    Orientation { mirrored: true, quarter_turns: 2 },
+    Orientation { mirrored: true, quarter_turns: 3 },
+];
+
+pub fn step(d: u8) -> (i32, i32) {
+    match d {
+        NORTH => (0, -1),
+        EAST => (1, 0),
+        SOUTH => (0, 1),
+        WEST => (-1, 0),
+        _ => (0, 0),
+    }
+}
+
+fn direction(o: (i32, i32)) -> u8 {
+    DIRECTIONS.iter().cloned().find(|d| step(*d) == o).unwrap()
+}
+
+impl Orientation {
+    // Where an offset ends up, with y pointing south.
+    pub fn apply(self, o: (i32, i32)) -> (i32, i32) {
+        let mut o = if self.mirrored { (-o.0, o.1) } else { o };
+        for _ in 0..self.quarter_turns {
+            o = (-o.1, o.0);
+        }
+        o
+    }
+
+    pub fn turn(self, d: u8) -> u8 {
+        direction(self.apply(step(d)))
+    }
+
+    // The move on the real board for a move in this orientation.
+    pub fn unturn(self, d: u8) -> u8 {
+        DIRECTIONS.iter().cloned().find(|real| self.turn(*real) == d).unwrap()
+    }
+}
+
+// The size x size window around loc with torus wrap, each site made into a T,
+// by offset in row order.
+pub fn cells<T, F>(map: &GameMap, loc: Location, size: i32, cell: F) -> Vec<((i32, i32), T)>
+    where F: Fn(&Site) -> T {
+    let half = size / 2;
+    let mut cells = Vec::with_capacity((size * size) as usize);
+    for dy in -half..half + 1 {
+        for dx in -half..half + 1 {
+            cells.push(((dx, dy), cell(map.get_site_ref(map.get_offset(loc, dx, dy), STILL))));
+        }
+    }
+    cells
+}
+
+// The orientation that makes the cells, read in row order, least, and the cells
+// read that way. The first of ORIENTATIONS wins ties.
+pub fn canonical<T: PartialOrd + Clone>(cells: &[((i32, i32), T)]) -> (Orientation, Vec<T>) {
+    let mut best: Option<(Orientation, Vec<T>)> = None;
+    for o in &ORIENTATIONS {
+        let mut turned = cells.iter().map(|&(at, ref c)| (o.apply(at), c.clone())).collect::<Vec<_>>();
+        turned.sort_by_key(|&(at, _)| (at.1, at.0));
+        let turned = turned.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
+        if best.as_ref().map_or(true, |b| turned < b.1) {
+            best = Some((*o, turned));
+        }
+    }
+    best.unwrap()
+}
+
+// CHANNELS values for every site of the window around loc, in canonical
+// orientation: 1 if ours, 1 if an enemy's, strength out of 255 and production
+// out of MAX_PRODUCTION.
+pub fn features(map: &GameMap, loc: Location, player: u8, size: i32) -> (Orientation, Vec<f32>) {
+    let cells = cells(map, loc, size, |site| [
+        if site.owner == player { 1.0 } else { 0.0 },
+        if site.owner != player && site.owner != 0 { 1.0 } else { 0.0 },
+        site.strength as f32 / 255.0,
+        (site.production as f32 / MAX_PRODUCTION).min(1.0),
+    ]);
+    let (o, turned) = canonical(&cells);
+    (o, turned.iter().flat_map(|c| c.iter().cloned()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario;
+
+    #[test]
+    fn turns_follow_the_orientation() {
+        for o in &ORIENTATIONS {
+            let mut turned = DIRECTIONS.iter().map(|d| o.turn(*d)).collect::<Vec<_>>();
+            turned.sort();
+            assert_eq!(turned, DIRECTIONS.to_vec());
+            assert!(DIRECTIONS.iter().all(|d| o.unturn(o.turn(*d)) == *d));
+        }
+        let quarter = ORIENTATIONS[1];
+        assert_eq!((quarter.turn(NORTH), quarter.turn(WEST), quarter.turn(STILL)), (EAST, NORTH, STILL));
+    }
+
+    // A position and the same position turned a quarter have the same features,
+    // and the same move on each board turns to the same canonical move.
+    #[test]
+    fn features_are_canonical() {
+        let map = scenario::parse("
+            .    .    .    .    .
+            .    .    2:60 .    .
+            .    1:50 1:50 .    .
+            .    .    .    .    .
+            .    .    .    .    .
+
+            1 1 1 1 1
+            1 1 9 1 1
+            1 4 1 1 1
+            1 1 1 1 1
+            1 1 1 1 1
+        ").unwrap();
+        let turned = scenario::parse("
+            .    .    .    .    .
+            .    .    1:50 .    .
+            .    .    1:50 2:60 .
+            .    .    .    .    .
+            .    .    .    .    .
+
+            1 1 1 1 1
+            1 1 4 1 1
+            1 1 1 9 1
+            1 1 1 1 1
+            1 1 1 1 1
+        ").unwrap();
+        let loc = Location { x: 2, y: 2 };
+        let (a, features_a) = features(&map, loc, 1, 5);
+        let (b, features_b) = features(&turned, loc, 1, 5);
+        assert_eq!(features_a, features_b);
+        assert_eq!(features_a.len(), 25 * CHANNELS);
+        assert_eq!(a.turn(NORTH), b.turn(EAST));
+        assert_eq!(a.turn(WEST), b.turn(NORTH));
+    }
+}
@@ -5,4 +5,14 @@ then
   rm "$f"
 fi
 
-zip "$f" Cargo.toml src/MyBot.rs src/hlt/* 
+# Weights read from the working directory, see src/policy.rs and src/scoring.rs.
+weights=""
+for w in policy.txt weights.txt
+do
+  if [ -e "$w" ]
+  then
+    weights="$weights $w"
+  fi
+done
+
+zip "$f" Cargo.toml src/*.rs src/hlt/* $weights